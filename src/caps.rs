@@ -0,0 +1,78 @@
+use std::io::Result;
+
+use crate::op::{Code, Op};
+use crate::uring::{Probe, Uring};
+use crate::{Feat, Setup};
+
+// What the running kernel supports, gathered once from the features of a
+// ring, its probe and trial rings built with each optional setup flag.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    features: Feat,
+    setup: Setup,
+    // None before 5.6, which cannot probe
+    probe: Option<Box<Probe>>,
+}
+
+impl Capabilities {
+    pub fn detect() -> Result<Self> {
+        let uring = Uring::entries(2).try_build()?;
+        let features = uring.features();
+        let probe = uring.probe().ok();
+        drop(uring);
+
+        let mut setup = Setup::empty();
+        for &flag in Setup::OPTIONAL.iter() {
+            if Uring::entries(2).setup(flag).try_build().is_ok() {
+                setup |= flag;
+            }
+        }
+        Ok(Self {
+            features,
+            setup,
+            probe,
+        })
+    }
+
+    #[inline]
+    pub fn features(&self) -> Feat {
+        self.features
+    }
+
+    // Whether the kernel accepts each of the optional setup `flags`, see
+    // UringBuilder::best_effort.
+    #[inline]
+    pub fn supports_setup(&self, flags: Setup) -> bool {
+        self.setup.contains(flags)
+    }
+
+    #[inline]
+    pub fn supports<T: Op>(&self) -> bool {
+        self.probe
+            .as_ref()
+            .is_some_and(|probe| probe.support::<T>())
+    }
+
+    #[inline]
+    pub fn supports_code(&self, code: Code) -> bool {
+        self.probe
+            .as_ref()
+            .is_some_and(|probe| probe.support_code(code))
+    }
+
+    #[inline]
+    pub fn probe(&self) -> Option<&Probe> {
+        self.probe.as_deref()
+    }
+
+    // Whether waits take their timeout directly, without a Timeout sqe.
+    #[inline]
+    pub fn ext_arg(&self) -> bool {
+        self.features.contains(Feat::EXT_ARG)
+    }
+
+    #[inline]
+    pub fn nodrop(&self) -> bool {
+        self.features.contains(Feat::NODROP)
+    }
+}
@@ -0,0 +1,315 @@
+use std::cmp;
+use std::future::Future;
+use std::io::{Error, Result};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::JoinHandle;
+
+use crate::driver::{self, Completion};
+use crate::op;
+use crate::uring::Fd;
+use crate::{Driver, EventfdNotifier, Uring};
+
+// Largest read submitted for one poll_read
+const MAX_READ: usize = 64 * 1024;
+
+// Offset telling read and write to use the file position
+const CURRENT: u64 = u64::MAX;
+
+// Drives a Driver from a tokio runtime.
+//
+// Completions are dispatched whenever the eventfd registered with the ring
+// becomes readable, so the ring never blocks the runtime. The driver is not
+// Send; `run` has to be spawned on a LocalSet, as `spawn` does.
+#[derive(Debug, Clone)]
+pub struct TokioDriver {
+    driver: Driver,
+    notifier: Rc<AsyncFd<EventfdNotifier>>,
+}
+
+impl TokioDriver {
+    // Has to be called within a runtime with IO enabled.
+    pub fn new(uring: Uring<'static>) -> Result<Self> {
+        let notifier = EventfdNotifier::register(&uring)?;
+        Ok(Self {
+            driver: Driver::new(uring),
+            notifier: Rc::new(AsyncFd::new(notifier)?),
+        })
+    }
+
+    #[inline]
+    pub fn driver(&self) -> &Driver {
+        &self.driver
+    }
+
+    // Dispatches completions until waiting on the eventfd fails. The errors
+    // of Driver::dispatch, such as lost completions, leave the driver usable
+    // and are dropped; see `run_with` to handle them.
+    #[inline]
+    pub async fn run(self) -> Result<()> {
+        self.run_with(drop).await
+    }
+
+    // As `run`, handing the errors of Driver::dispatch to `on_error` and
+    // dispatching on, so that the ops still in flight complete.
+    pub async fn run_with<F: FnMut(Error)>(self, mut on_error: F) -> Result<()> {
+        loop {
+            let mut guard = self.notifier.readable().await?;
+            guard.get_inner().clear()?;
+            guard.clear_ready();
+            if let Err(err) = self.driver.dispatch() {
+                on_error(err);
+            }
+        }
+    }
+
+    // Spawns `run` on the current LocalSet.
+    #[inline]
+    pub fn spawn(&self) -> JoinHandle<Result<()>> {
+        tokio::task::spawn_local(self.clone().run())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Kind {
+    File,
+    Socket,
+}
+
+// Buffers owned by in-flight reads and writes, shared by File and Socket
+#[derive(Debug)]
+struct Io {
+    driver: Driver,
+    fd: Fd,
+    kind: Kind,
+    read: Option<Completion<Vec<u8>>>,
+    // Bytes read beyond what the last poll_read could take
+    rbuf: Vec<u8>,
+    rpos: usize,
+    write: Option<Completion<Vec<u8>>>,
+}
+
+impl Io {
+    #[inline]
+    fn new(driver: &Driver, fd: RawFd, kind: Kind) -> Self {
+        Self {
+            driver: driver.clone(),
+            fd: Fd::new(fd),
+            kind,
+            read: None,
+            rbuf: Vec::new(),
+            rpos: 0,
+            write: None,
+        }
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        if self.rpos < self.rbuf.len() {
+            let n = cmp::min(buf.remaining(), self.rbuf.len() - self.rpos);
+            buf.put_slice(&self.rbuf[self.rpos..self.rpos + n]);
+            self.rpos += n;
+            return Poll::Ready(Ok(()));
+        }
+        if self.read.is_none() {
+            let mut data = vec![0; cmp::min(buf.remaining(), MAX_READ)];
+            let fd = self.fd.as_raw_fd();
+            // The heap buffer of data does not move with it
+            let buf = unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr(), data.len()) };
+            let completion = unsafe {
+                match self.kind {
+                    Kind::File => self.driver.submit(
+                        op::Read {
+                            fd,
+                            buf,
+                            offset: CURRENT,
+                        },
+                        data,
+                    ),
+                    Kind::Socket => self.driver.submit(
+                        op::Recv {
+                            sockfd: fd,
+                            buf,
+                            flags: 0,
+                        },
+                        data,
+                    ),
+                }
+            };
+            self.read = Some(completion);
+        }
+        let (cqe, mut data) = match Pin::new(self.read.as_mut().unwrap()).poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.read = None;
+        let n = driver::cvt(&cqe)? as usize;
+        data.truncate(n);
+        let taken = cmp::min(buf.remaining(), n);
+        buf.put_slice(&data[..taken]);
+        self.rbuf = data;
+        self.rpos = taken;
+        Poll::Ready(Ok(()))
+    }
+
+    // A write left pending is completed by the next call, whatever `buf` is
+    // then, as with any writer that buffers internally.
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        if self.write.is_none() {
+            let data = buf.to_vec();
+            let fd = self.fd.as_raw_fd();
+            let slice = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) };
+            let completion = unsafe {
+                match self.kind {
+                    Kind::File => self.driver.submit(
+                        op::Write {
+                            fd,
+                            data: slice,
+                            offset: CURRENT,
+                        },
+                        data,
+                    ),
+                    Kind::Socket => self.driver.submit(
+                        op::Send {
+                            sockfd: fd,
+                            data: slice,
+                            flags: libc::MSG_NOSIGNAL as u32,
+                        },
+                        data,
+                    ),
+                }
+            };
+            self.write = Some(completion);
+        }
+        let (cqe, _) = match Pin::new(self.write.as_mut().unwrap()).poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.write = None;
+        Poll::Ready(driver::cvt(&cqe).map(|n| n as usize))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.write.as_mut() {
+            Some(write) => {
+                let (cqe, _) = match Pin::new(write).poll(cx) {
+                    Poll::Ready(output) => output,
+                    Poll::Pending => return Poll::Pending,
+                };
+                self.write = None;
+                Poll::Ready(driver::cvt(&cqe).map(drop))
+            }
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+// A file, pipe or character device read and written through the ring at its
+// current position.
+#[derive(Debug)]
+pub struct File {
+    io: Io,
+}
+
+impl File {
+    #[inline]
+    pub fn new<T: IntoRawFd>(driver: &Driver, file: T) -> Self {
+        Self {
+            io: Io::new(driver, file.into_raw_fd(), Kind::File),
+        }
+    }
+}
+
+impl AsRawFd for File {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.fd.as_raw_fd()
+    }
+}
+
+impl AsyncRead for File {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut().io.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for File {
+    #[inline]
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().io.poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().io.poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().io.poll_flush(cx)
+    }
+}
+
+// A connected stream socket read with op::Recv and written with op::Send.
+#[derive(Debug)]
+pub struct Socket {
+    io: Io,
+}
+
+impl Socket {
+    #[inline]
+    pub fn new<T: IntoRawFd>(driver: &Driver, socket: T) -> Self {
+        Self {
+            io: Io::new(driver, socket.into_raw_fd(), Kind::Socket),
+        }
+    }
+}
+
+impl AsRawFd for Socket {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.fd.as_raw_fd()
+    }
+}
+
+impl AsyncRead for Socket {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut().io.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Socket {
+    #[inline]
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().io.poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().io.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let io = &mut self.get_mut().io;
+        match io.poll_flush(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        let ret = unsafe { libc::shutdown(io.fd.as_raw_fd(), libc::SHUT_WR) };
+        Poll::Ready(crate::sys::cvt(ret).map(drop))
+    }
+}
@@ -1,22 +1,27 @@
+use std::error;
+use std::fmt;
 use std::io::{Error, Result};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicU32, Ordering};
 
+#[cfg(feature = "fault-injection")]
+use crate::fault::FaultInjector;
 use crate::params::UringParams;
 use crate::sys;
 use crate::uring::Mmap;
+use crate::UserData;
 
 // struct io_cqring_offsets
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct Offsets {
-    head: u32,
-    tail: u32,
-    ring_mask: u32,
-    ring_entries: u32,
-    overflow: u32,
-    cqes: u32,
-    flags: u32,
+    pub(crate) head: u32,
+    pub(crate) tail: u32,
+    pub(crate) ring_mask: u32,
+    pub(crate) ring_entries: u32,
+    pub(crate) overflow: u32,
+    pub(crate) cqes: u32,
+    pub(crate) flags: u32,
     _resv1: u32,
     _resv2: u64,
 }
@@ -40,9 +45,19 @@ pub struct Entry {
 
 impl Entry {
     const F_BUFFER: u32 = 1 << 0;
+    const F_MORE: u32 = 1 << 1;
 
     const BUFFER_SHIFT: u32 = 16;
 
+    #[inline]
+    pub(crate) const fn new(user_data: u64, res: i32, flags: u32) -> Self {
+        Self {
+            user_data,
+            res,
+            flags,
+        }
+    }
+
     #[inline]
     pub fn user_data(&self) -> u64 {
         self.user_data
@@ -53,6 +68,18 @@ impl Entry {
         self.res
     }
 
+    #[cfg(feature = "fault-injection")]
+    #[inline]
+    pub(crate) fn set_res(&mut self, res: i32) {
+        self.res = res;
+    }
+
+    // Whether a multishot request will post more completions.
+    #[inline]
+    pub fn more(&self) -> bool {
+        self.flags & Self::F_MORE != 0
+    }
+
     #[inline]
     pub fn buffer_id(&self) -> Option<u16> {
         if self.flags & Self::F_BUFFER != 0 {
@@ -63,6 +90,22 @@ impl Entry {
     }
 }
 
+// Completions the kernel dropped because the CQ ring was full and, with
+// Feat::NODROP, it could not hold them back either. The ops they belong to
+// never complete.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Overflowed {
+    pub lost: u32,
+}
+
+impl fmt::Display for Overflowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} completions lost to a full CQ ring", self.lost)
+    }
+}
+
+impl error::Error for Overflowed {}
+
 #[derive(Debug)]
 pub struct Queue<'a> {
     khead: &'a AtomicU32,
@@ -72,15 +115,19 @@ pub struct Queue<'a> {
     kflags: Option<&'a AtomicU32>,
     koverflow: &'a AtomicU32,
     cqes: *const Entry,
+    // koverflow when last reported
+    overflow_seen: u32,
 
     khead_shadow: u32,
     ktail_shadow: u32,
 
     ring_ptr: Rc<Mmap<libc::c_void>>,
+
+    #[cfg(feature = "fault-injection")]
+    pub(crate) faults: Option<FaultInjector>,
 }
 
 impl Queue<'_> {
-    pub(crate) const UDATA_TIMEOUT: u64 = -1i64 as u64;
     const F_EVENTFD_DISABLED: u32 = 1 << 0;
 
     #[inline]
@@ -95,28 +142,60 @@ impl Queue<'_> {
             } else {
                 Some(&*(ptr.add(cq_off.flags as usize) as *const AtomicU32))
             };
+            let koverflow = &*(ptr.add(cq_off.overflow as usize) as *const AtomicU32);
             Self {
                 khead,
                 ktail,
                 kring_mask: *(ptr.add(cq_off.ring_mask as usize) as *const u32),
                 kring_entries: *(ptr.add(cq_off.ring_entries as usize) as *const u32),
                 kflags,
-                koverflow: &*(ptr.add(cq_off.overflow as usize) as *const AtomicU32),
+                koverflow,
                 cqes: ptr.add(cq_off.cqes as usize) as *const Entry,
+                overflow_seen: koverflow.load(Ordering::Relaxed),
 
                 khead_shadow: khead.load(Ordering::Relaxed),
                 ktail_shadow: ktail.load(Ordering::Acquire),
 
                 ring_ptr,
+
+                #[cfg(feature = "fault-injection")]
+                faults: None,
             }
         }
     }
 
+    #[inline]
+    pub fn entries(&self) -> u32 {
+        self.kring_entries
+    }
+
+    // Number of cqes posted and not reaped yet.
+    #[inline]
+    pub fn ready(&self) -> u32 {
+        self.ktail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.khead_shadow)
+    }
+
+    // Total of completions dropped since the ring was set up.
     #[inline]
     pub fn overflow(&self) -> u32 {
         self.koverflow.load(Ordering::Relaxed)
     }
 
+    // Completions dropped since the last call, if any.
+    #[inline]
+    pub fn take_overflowed(&mut self) -> Option<Overflowed> {
+        let overflow = self.overflow();
+        let lost = overflow.wrapping_sub(self.overflow_seen);
+        self.overflow_seen = overflow;
+        if lost > 0 {
+            Some(Overflowed { lost })
+        } else {
+            None
+        }
+    }
+
     #[inline]
     pub fn advance(&mut self, n: u32) {
         if n > 0 {
@@ -160,21 +239,45 @@ impl Queue<'_> {
                     return Ok(None);
                 }
             }
+            #[cfg(feature = "fault-injection")]
+            self.inject_fault();
             let cqe = unsafe {
                 &*(self
                     .cqes
                     .add((self.khead_shadow & self.kring_mask) as usize))
             };
-            if cqe.user_data == Self::UDATA_TIMEOUT {
+            let user_data = UserData::from(cqe.user_data);
+            if user_data == UserData::TIMEOUT {
                 let err = cqe.res;
                 self.advance(1);
                 sys::cvt(err)?;
+            } else if user_data.is_internal() {
+                self.advance(1);
             } else {
                 return Ok(Some(cqe));
             }
         }
     }
 
+    // Rewrites the cqe at head in place, and the one after it if posted
+    #[cfg(feature = "fault-injection")]
+    fn inject_fault(&mut self) {
+        if let Some(faults) = &self.faults {
+            let cqes = self.cqes as *mut Entry;
+            let head = self.khead_shadow;
+            let next = head.wrapping_add(1);
+            unsafe {
+                let cqe = &mut *cqes.add((head & self.kring_mask) as usize);
+                let next = if next != self.ktail_shadow {
+                    Some(&mut *cqes.add((next & self.kring_mask) as usize))
+                } else {
+                    None
+                };
+                faults.apply(head, cqe, next);
+            }
+        }
+    }
+
     #[inline]
     pub(crate) fn ring_ptr(&self) -> &Mmap<libc::c_void> {
         &self.ring_ptr
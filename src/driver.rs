@@ -0,0 +1,689 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use ruyi_slab::Slab;
+
+use crate::op::{self, Op};
+use crate::uring::Probe;
+use crate::{cq, Uring, UserData};
+
+enum Lifecycle {
+    Submitted,
+    Waiting(Waker),
+    Completed(cq::Entry),
+    // A multishot op, with the cqes not taken yet
+    Multi {
+        cqes: VecDeque<cq::Entry>,
+        waker: Option<Waker>,
+    },
+    // The Completion was dropped, the data is kept until the kernel is done.
+    // The cqes still to come go to `discard`, if any.
+    Ignored {
+        #[allow(dead_code)]
+        data: Box<dyn Any>,
+        discard: Option<Discard>,
+    },
+}
+
+// Releases what a cqe nobody takes carries, such as the fd of an accept
+pub(crate) type Discard = fn(&cq::Entry);
+
+// IOSQE_IO_LINK, the next sqe starts once this one has completed
+const IO_LINK: u8 = 1 << 2;
+
+// An op kept on the heap until its last cqe, as the kernel may read memory
+// inline in it, such as the timespec of op::Timeout, until then: its sqe can
+// stay queued past a failed enter, and SQPOLL reads sqes after the enter.
+trait Held {}
+
+impl<T> Held for T {}
+
+// Erases the lifetime of `op`, which the caller of submit guarantees the
+// borrowed memory outlives the op's completion.
+#[inline]
+unsafe fn hold<'a, T: 'a>(op: Box<T>) -> Box<dyn Held> {
+    let op: Box<dyn Held + 'a> = op;
+    std::mem::transmute::<Box<dyn Held + 'a>, Box<dyn Held>>(op)
+}
+
+struct Inner {
+    uring: Uring<'static>,
+    ops: Slab<Lifecycle>,
+    probe: Option<Box<Probe>>,
+    // A submission failed after its sqes were queued, reported by dispatch
+    error: Option<Error>,
+}
+
+impl Inner {
+    // Completes the ops of the cqes reaped, collecting the wakers to wake
+    // once the driver is no longer borrowed.
+    fn reap(&mut self, wakers: &mut Vec<Waker>) -> Result<u32> {
+        let Inner { uring, ops, .. } = self;
+        uring.reap(|cqe| {
+            let key = match UserData::from(cqe.user_data()).key() {
+                Some(key) => key,
+                None => return,
+            };
+            match ops.get_mut(key) {
+                Some(Lifecycle::Ignored { discard, .. }) => {
+                    if let Some(discard) = discard {
+                        discard(&cqe);
+                    }
+                    if !cqe.more() {
+                        ops.remove(key);
+                    }
+                }
+                Some(Lifecycle::Multi { cqes, waker }) => {
+                    cqes.push_back(cqe);
+                    if let Some(waker) = waker.take() {
+                        wakers.push(waker);
+                    }
+                }
+                Some(lifecycle) => {
+                    if let Lifecycle::Waiting(waker) =
+                        std::mem::replace(lifecycle, Lifecycle::Completed(cqe))
+                    {
+                        wakers.push(waker);
+                    }
+                }
+                None => {}
+            }
+        })
+    }
+
+    // Submits the queued sqes. EBUSY means the kernel holds back as many
+    // cqes as it can: the CQ ring is reaped to make room and the submission
+    // retried. Should it fail again, the sqes stay queued for `park`.
+    fn submit(&mut self, wakers: &mut Vec<Waker>) -> Result<()> {
+        match self.uring.submit() {
+            Err(ref err) if is_busy(err) => {
+                self.reap(wakers)?;
+                match self.uring.submit() {
+                    Err(ref err) if is_busy(err) => Ok(()),
+                    res => res.map(drop),
+                }
+            }
+            res => res.map(drop),
+        }
+    }
+
+    // Submits ops already prepared. They are in flight whatever happens: the
+    // sqes are in the SQ ring and go with the next enter, so an error is
+    // kept for `dispatch` rather than failing the ops.
+    fn submit_prepared(&mut self, wakers: &mut Vec<Waker>) {
+        match self.submit(wakers) {
+            Err(ref err) if is_transient(err) => {}
+            Err(err) => self.error = Some(err),
+            Ok(()) => {}
+        }
+    }
+}
+
+// Runs ops as futures on a ring.
+//
+// Every op is submitted as soon as it is created, its Completion resolves
+// once `dispatch` has seen its cqe. Something has to keep calling `dispatch`:
+// `park` and `block_on` do so by waiting on the ring, an event loop can do
+// so whenever an EventfdNotifier of the ring fires.
+#[derive(Clone)]
+pub struct Driver {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Driver {
+    #[inline]
+    pub fn new(uring: Uring<'static>) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                uring,
+                ops: Slab::new(),
+                probe: None,
+                error: None,
+            })),
+        }
+    }
+
+    /// Prepares and submits `op`. `data` is handed back with the cqe once the
+    /// op completes; if the Completion is dropped first, `data` is kept alive
+    /// until then. So is `op`, boxed before it is prepared, so memory inline
+    /// in it such as a timespec stays put.
+    ///
+    /// # Safety
+    ///
+    /// Any other memory the op refers to must be owned by `data`, at an
+    /// address that does not change when `data` is moved, or outlive the op's
+    /// completion. The op must post a single cqe, multishot ops are not
+    /// supported.
+    pub unsafe fn submit<T: Op, D: 'static>(&self, op: T, data: D) -> Completion<D> {
+        let mut op = Box::new(op);
+        let data = Box::new(data);
+        let state = match self.push(&mut *op, Lifecycle::Submitted) {
+            Ok(key) => State::InFlight(key),
+            Err(cqe) => State::Failed(cqe),
+        };
+        Completion {
+            driver: self.clone(),
+            state,
+            data: Some(data),
+            op: Some(hold(op)),
+            discard: None,
+        }
+    }
+
+    /// Prepares and submits an `op` that owns the memory it refers to, such
+    /// as op::GetXattr, handing it back with the cqe. The op is boxed before
+    /// it is prepared and not moved until it completes, so the memory may be
+    /// inline in it.
+    ///
+    /// # Safety
+    ///
+    /// Any memory the op borrows must outlive the op's completion.
+    pub unsafe fn submit_owned<T: Op + 'static>(&self, op: T) -> Completion<T> {
+        let mut op = Box::new(op);
+        let state = match self.push(&mut *op, Lifecycle::Submitted) {
+            Ok(key) => State::InFlight(key),
+            Err(cqe) => State::Failed(cqe),
+        };
+        Completion {
+            driver: self.clone(),
+            state,
+            data: Some(op),
+            op: None,
+            discard: None,
+        }
+    }
+
+    /// Prepares and submits a multishot `op`, whose completions are taken
+    /// with `Multishot::next`. Dropping the Multishot cancels the op.
+    ///
+    /// # Safety
+    ///
+    /// As for `submit`.
+    pub unsafe fn submit_multi<T: Op, D: 'static>(&self, op: T, data: D) -> Multishot<D> {
+        let mut op = Box::new(op);
+        let data = Box::new(data);
+        let multi = Lifecycle::Multi {
+            cqes: VecDeque::new(),
+            waker: None,
+        };
+        let (key, cqes) = match self.push(&mut *op, multi) {
+            Ok(key) => (Some(key), VecDeque::new()),
+            Err(cqe) => (None, VecDeque::from(vec![cqe])),
+        };
+        Multishot {
+            driver: self.clone(),
+            key,
+            cqes,
+            data: Some(data),
+            op: Some(hold(op)),
+            discard: None,
+        }
+    }
+
+    /// Prepares `first` and `second` as a link and submits them: `second`
+    /// starts once `first` has completed, or completes with -ECANCELED if
+    /// `first` failed or completed short.
+    ///
+    /// Both ops are kept until they complete, as with `submit`.
+    ///
+    /// # Safety
+    ///
+    /// As for `submit`, with `data` owning the memory of both ops.
+    pub unsafe fn submit_linked<T: Op, U: Op, D: 'static>(
+        &self,
+        first: T,
+        second: U,
+        data: D,
+    ) -> (Completion<()>, Completion<D>) {
+        let mut first = Box::new(first);
+        let mut second = Box::new(second);
+        let data = Box::new(data);
+        let mut wakers = Vec::new();
+        let keys = {
+            let mut inner = self.inner.borrow_mut();
+            let inner = &mut *inner;
+            if inner.uring.as_sq().space_left() < 2 {
+                inner.submit(&mut wakers).ok();
+            }
+            if inner.uring.as_sq().space_left() < 2 {
+                Err(Error::from_raw_os_error(libc::EBUSY))
+            } else {
+                let first_key = inner.ops.insert(Lifecycle::Submitted);
+                let second_key = inner.ops.insert(Lifecycle::Submitted);
+                let user_data = UserData::from_key(first_key).into();
+                let sqe = inner.uring.prepare(&mut *first).unwrap();
+                sqe.set_user_data(user_data);
+                sqe.set_flags(IO_LINK);
+                let user_data = UserData::from_key(second_key).into();
+                Self::prepare(&mut inner.uring, &mut *second, user_data);
+                inner.submit_prepared(&mut wakers);
+                Ok((first_key, second_key))
+            }
+        };
+        wakers.into_iter().for_each(Waker::wake);
+        let (first_op, second_op) = (first, second);
+        let (first, second) = match keys {
+            Ok((first, second)) => (State::InFlight(first), State::InFlight(second)),
+            Err(err) => {
+                let errno = err.raw_os_error().unwrap_or(libc::EIO);
+                let cqe = cq::Entry::new(0, -errno, 0);
+                (State::Failed(cqe), State::Failed(cqe))
+            }
+        };
+        (
+            Completion {
+                driver: self.clone(),
+                state: first,
+                data: Some(Box::new(())),
+                op: Some(hold(first_op)),
+                discard: None,
+            },
+            Completion {
+                driver: self.clone(),
+                state: second,
+                data: Some(data),
+                op: Some(hold(second_op)),
+                discard: None,
+            },
+        )
+    }
+
+    // Returns the key of the op, or the cqe to fail it with.
+    unsafe fn push<T: Op>(
+        &self,
+        op: &mut T,
+        lifecycle: Lifecycle,
+    ) -> std::result::Result<usize, cq::Entry> {
+        let mut wakers = Vec::new();
+        let res = {
+            let mut inner = self.inner.borrow_mut();
+            let inner = &mut *inner;
+            let key = inner.ops.insert(lifecycle);
+            let user_data = UserData::from_key(key).into();
+
+            let mut prepared = Self::prepare(&mut inner.uring, op, user_data);
+            if !prepared && inner.submit(&mut wakers).is_ok() {
+                prepared = Self::prepare(&mut inner.uring, op, user_data);
+            }
+            if prepared {
+                inner.submit_prepared(&mut wakers);
+                Ok(key)
+            } else {
+                // The SQ ring is full, the op never reached the kernel
+                inner.ops.remove(key);
+                Err(cq::Entry::new(user_data, -libc::EBUSY, 0))
+            }
+        };
+        wakers.into_iter().for_each(Waker::wake);
+        res
+    }
+
+    #[inline]
+    fn prepare<T: Op>(uring: &mut Uring<'_>, op: &mut T, user_data: u64) -> bool {
+        match unsafe { uring.prepare(op) } {
+            Some(sqe) => {
+                sqe.set_user_data(user_data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Completes the ops whose cqes are in the CQ ring or, with Feat::NODROP,
+    // held back for it. Returns the number of cqes handled.
+    //
+    // Once the cqes are handled, fails with a cq::Overflowed error if the
+    // kernel dropped cqes since the last call: the ops they belong to never
+    // complete. Otherwise fails with the error of a submission made since
+    // the last call; the ops submitted stay in flight, their sqes going
+    // with the next enter.
+    pub fn dispatch(&self) -> Result<u32> {
+        let mut wakers = Vec::new();
+        let res = {
+            let mut inner = self.inner.borrow_mut();
+            let res = inner.reap(&mut wakers);
+            if res.is_err() {
+                res
+            } else if let Some(overflowed) = inner.uring.overflowed() {
+                Err(Error::other(overflowed))
+            } else if let Some(err) = inner.error.take() {
+                Err(err)
+            } else {
+                res
+            }
+        };
+        wakers.into_iter().for_each(Waker::wake);
+        res
+    }
+
+    // Waits for at least one cqe and dispatches it.
+    pub fn park(&self) -> Result<u32> {
+        let res = self.inner.borrow_mut().uring.submit_and_wait(1);
+        match res {
+            // Reaping makes room for the cqes held back
+            Err(ref err) if is_busy(err) => {}
+            Err(ref err) if err.kind() != ErrorKind::Interrupted => return res,
+            _ => {}
+        }
+        self.dispatch()
+    }
+
+    // Runs `future` to completion on the current thread, parking on the ring
+    // while it is pending.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        struct Flag(AtomicBool);
+
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::Release);
+            }
+        }
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            if !flag.0.swap(false, Ordering::Acquire) {
+                self.park().expect("failed to wait on the ring");
+            }
+        }
+    }
+
+    // Number of ops submitted whose results have not been taken yet,
+    // including those whose Completion was dropped before the op completed.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.inner.borrow().ops.len()
+    }
+
+    // Whether the kernel supports `T`, probed once per driver.
+    pub fn supports<T: Op>(&self) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        if inner.probe.is_none() {
+            match inner.uring.probe() {
+                Ok(probe) => inner.probe = Some(probe),
+                Err(_) => return false,
+            }
+        }
+        inner.probe.as_ref().unwrap().support::<T>()
+    }
+
+    // Closes `fd` with op::Close without waiting for the result, falling
+    // back to close(2) if the ring is full.
+    pub(crate) fn close(&self, fd: RawFd) {
+        let completion = unsafe { self.submit(op::Close { fd }, ()) };
+        if completion.is_failed() {
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    #[inline]
+    pub fn with_uring<R>(&self, f: impl FnOnce(&mut Uring<'static>) -> R) -> R {
+        f(&mut self.inner.borrow_mut().uring)
+    }
+}
+
+impl fmt::Debug for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Driver {{ ops: {} }}", self.inner.borrow().ops.len())
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    InFlight(usize),
+    Failed(cq::Entry),
+    Done,
+}
+
+// The pending result of an op submitted with `Driver::submit`.
+pub struct Completion<D: 'static> {
+    driver: Driver,
+    state: State,
+    data: Option<Box<D>>,
+    // The op, unless it is the data, see `submit_owned`
+    op: Option<Box<dyn Held>>,
+    discard: Option<Discard>,
+}
+
+impl<D: 'static> Completion<D> {
+    // Whether the op could not be submitted, the result is then ready.
+    #[inline]
+    pub(crate) fn is_failed(&self) -> bool {
+        matches!(self.state, State::Failed(_))
+    }
+
+    // Hands the cqe to `discard` should the Completion be dropped before its
+    // result is taken.
+    #[inline]
+    pub(crate) fn discard_with(mut self, discard: Discard) -> Self {
+        self.discard = Some(discard);
+        self
+    }
+
+    // Drops the Completion without dropping its data.
+    #[inline]
+    pub(crate) fn leak_data(mut self) {
+        if let Some(data) = self.data.take() {
+            Box::leak(data);
+        }
+    }
+}
+
+impl<D: 'static> Future for Completion<D> {
+    type Output = (cq::Entry, D);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let cqe = match this.state {
+            State::InFlight(key) => {
+                let mut inner = this.driver.inner.borrow_mut();
+                let lifecycle = &mut inner.ops[key];
+                match lifecycle {
+                    Lifecycle::Completed(cqe) => {
+                        let cqe = *cqe;
+                        inner.ops.remove(key);
+                        cqe
+                    }
+                    Lifecycle::Waiting(waker) if waker.will_wake(cx.waker()) => {
+                        return Poll::Pending;
+                    }
+                    _ => {
+                        *lifecycle = Lifecycle::Waiting(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                }
+            }
+            State::Failed(cqe) => cqe,
+            State::Done => panic!("Completion polled after completion"),
+        };
+        this.state = State::Done;
+        this.op = None;
+        let data = this.data.take().unwrap();
+        Poll::Ready((cqe, *data))
+    }
+}
+
+impl<D: 'static> Drop for Completion<D> {
+    fn drop(&mut self) {
+        if let State::InFlight(key) = self.state {
+            let mut inner = self.driver.inner.borrow_mut();
+            match inner.ops.get(key) {
+                Some(Lifecycle::Completed(cqe)) => {
+                    if let Some(discard) = self.discard {
+                        discard(cqe);
+                    }
+                    inner.ops.remove(key);
+                }
+                Some(_) => {
+                    let held = (self.data.take().unwrap(), self.op.take());
+                    inner.ops[key] = Lifecycle::Ignored {
+                        data: Box::new(held),
+                        discard: self.discard,
+                    };
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+impl<D: 'static> fmt::Debug for Completion<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Completion {{ state: {:?} }}", self.state)
+    }
+}
+
+// The results of a multishot op submitted with `Driver::submit_multi`.
+pub struct Multishot<D: 'static> {
+    driver: Driver,
+    // None once the op is done
+    key: Option<usize>,
+    cqes: VecDeque<cq::Entry>,
+    data: Option<Box<D>>,
+    op: Option<Box<dyn Held>>,
+    discard: Option<Discard>,
+}
+
+impl<D: 'static> Multishot<D> {
+    // Returns the next cqe, None once the op has posted its last one.
+    #[inline]
+    pub async fn next(&mut self) -> Option<cq::Entry> {
+        std::future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<cq::Entry>> {
+        if let Some(cqe) = self.cqes.pop_front() {
+            return Poll::Ready(Some(cqe));
+        }
+        let key = match self.key {
+            Some(key) => key,
+            None => return Poll::Ready(None),
+        };
+        let mut inner = self.driver.inner.borrow_mut();
+        if let Lifecycle::Multi { cqes, waker } = &mut inner.ops[key] {
+            match cqes.pop_front() {
+                Some(cqe) => {
+                    if !cqe.more() {
+                        inner.ops.remove(key);
+                        self.key = None;
+                    }
+                    Poll::Ready(Some(cqe))
+                }
+                None => {
+                    *waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    // Hands the cqes not taken yet, and those still to come, to `discard`
+    // should the Multishot be dropped.
+    #[inline]
+    pub(crate) fn discard_with(mut self, discard: Discard) -> Self {
+        self.discard = Some(discard);
+        self
+    }
+
+    #[inline]
+    pub fn data(&self) -> &D {
+        self.data.as_ref().unwrap()
+    }
+
+    // Whether the op has posted its last cqe.
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.key.is_none()
+    }
+}
+
+impl<D: 'static> Drop for Multishot<D> {
+    fn drop(&mut self) {
+        if let Some(discard) = self.discard {
+            self.cqes.iter().for_each(discard);
+        }
+        let key = match self.key {
+            Some(key) => key,
+            None => return,
+        };
+        let mut inner = self.driver.inner.borrow_mut();
+        let inner = &mut *inner;
+        let done = match &inner.ops[key] {
+            Lifecycle::Multi { cqes, .. } => {
+                if let Some(discard) = self.discard {
+                    cqes.iter().for_each(discard);
+                }
+                cqes.back().is_some_and(|cqe| !cqe.more())
+            }
+            _ => unreachable!(),
+        };
+        if done {
+            inner.ops.remove(key);
+            return;
+        }
+        let held = (self.data.take().unwrap(), self.op.take());
+        inner.ops[key] = Lifecycle::Ignored {
+            data: Box::new(held),
+            discard: self.discard,
+        };
+        let mut cancel = op::Cancel::user_data(UserData::from_key(key).into());
+        Self::cancel(&mut inner.uring, &mut cancel);
+    }
+}
+
+impl<D: 'static> Multishot<D> {
+    #[inline]
+    fn cancel(uring: &mut Uring<'_>, cancel: &mut op::Cancel) {
+        if !Driver::prepare(uring, cancel, UserData::CANCEL.into()) {
+            uring.submit().ok();
+            Driver::prepare(uring, cancel, UserData::CANCEL.into());
+        }
+        uring.submit().ok();
+    }
+}
+
+impl<D: 'static> fmt::Debug for Multishot<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Multishot {{ key: {:?} }}", self.key)
+    }
+}
+
+// The kernel refused new sqes until the cqes it holds back are reaped
+#[inline]
+fn is_busy(err: &Error) -> bool {
+    err.raw_os_error() == Some(libc::EBUSY)
+}
+
+// Enter errors that the next enter retries, such as an interrupted wait
+#[inline]
+fn is_transient(err: &Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EAGAIN) | Some(libc::EINTR))
+}
+
+// Converts the result of a cqe into an io::Result.
+#[inline]
+pub(crate) fn cvt(cqe: &cq::Entry) -> Result<u32> {
+    if cqe.res() >= 0 {
+        Ok(cqe.res() as u32)
+    } else {
+        Err(Error::from_raw_os_error(-cqe.res()))
+    }
+}
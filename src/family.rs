@@ -0,0 +1,83 @@
+use std::io::{Error, Result};
+use std::mem;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::{sys, Uring, UringBuilder};
+
+// Builds rings sharing the io-wq of one ring, typically one ring per thread.
+//
+// The family holds a reference to the file of the ring it was created from,
+// so members can be built after that ring is dropped.
+#[derive(Debug, Clone)]
+pub struct WqFamily {
+    builder: UringBuilder,
+}
+
+impl WqFamily {
+    // Members are built with the settings of `builder`, attached to the
+    // io-wq of `uring`.
+    #[inline]
+    pub fn new(uring: &Uring<'_>, builder: &UringBuilder) -> Self {
+        let mut builder = builder.clone();
+        builder.share_wq(uring.shared_fd().clone());
+        Self { builder }
+    }
+
+    #[inline]
+    pub fn try_build<'a>(&self) -> Result<Uring<'a>> {
+        self.builder.try_build()
+    }
+
+    // Spawns one thread per CPU the calling thread may run on, see
+    // `spawn_on`.
+    pub fn spawn_per_core<F, T>(&self, f: F) -> Result<Vec<JoinHandle<Result<T>>>>
+    where
+        F: Fn(usize, Uring<'_>) -> T + Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_on(&affinity()?, f)
+    }
+
+    // Spawns a thread pinned to each of `cpus`, builds a member ring on it
+    // and runs `f` with the CPU and the ring.
+    pub fn spawn_on<F, T>(&self, cpus: &[usize], f: F) -> Result<Vec<JoinHandle<Result<T>>>>
+    where
+        F: Fn(usize, Uring<'_>) -> T + Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        let f = Arc::new(f);
+        cpus.iter()
+            .map(|&cpu| {
+                let family = self.clone();
+                let f = f.clone();
+                thread::Builder::new()
+                    .name(format!("ur-{}", cpu))
+                    .spawn(move || {
+                        pin(cpu)?;
+                        let uring = family.try_build()?;
+                        Ok(f(cpu, uring))
+                    })
+            })
+            .collect()
+    }
+}
+
+fn affinity() -> Result<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    let ret = unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) };
+    sys::cvt(ret)?;
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+fn pin(cpu: usize) -> Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    let ret = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) };
+    sys::cvt(ret).map(drop)
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::op::Code;
+use crate::{cq, UserData};
+
+// What a rule does to a completion.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    // Replaces the result with -errno, such as EAGAIN, EINTR or ECANCELED.
+    // The op may still have taken effect.
+    Error(i32),
+    // Shortens a result above 1 to a random length below it, as a short
+    // read or write
+    Short,
+    // Swaps the completion with the one after it, if already posted
+    Reorder,
+}
+
+// Rewrites completions of a ring before they are peeked, to test how code
+// copes with results the kernel rarely produces. Installed with
+// Uring::inject_faults; clones share the rules and the counters.
+//
+// Each rule applies a Fault to completions of ops with a given op::Code,
+// with a probability drawn from a generator seeded by `new`, so a run can
+// be replayed.
+#[derive(Clone)]
+pub struct FaultInjector {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl FaultInjector {
+    #[inline]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                // xorshift gets stuck at 0
+                state: seed | 1,
+                rules: Vec::new(),
+                ops: HashMap::new(),
+                decided: None,
+                injected: 0,
+            })),
+        }
+    }
+
+    // Applies `fault` to a completion of `code` with `probability`, between
+    // 0 and 1. Rules are tried in the order added; the first that fires
+    // wins.
+    pub fn rule(&self, code: Code, fault: Fault, probability: f64) -> &Self {
+        self.inner.borrow_mut().rules.push(Rule {
+            code: code as u8,
+            fault,
+            probability,
+        });
+        self
+    }
+
+    #[inline]
+    pub fn clear(&self) {
+        self.inner.borrow_mut().rules.clear();
+    }
+
+    // Number of completions rewritten so far
+    #[inline]
+    pub fn injected(&self) -> u64 {
+        self.inner.borrow().injected
+    }
+
+    // Records the op of a sqe about to be submitted.
+    #[inline]
+    pub(crate) fn submitted(&self, user_data: u64, opcode: u8) {
+        if !UserData::from(user_data).is_internal() {
+            self.inner.borrow_mut().ops.insert(user_data, opcode);
+        }
+    }
+
+    // Rewrites the cqe at `head`, `next` being the one after it if posted.
+    // Each cqe is decided once, however often it is peeked.
+    pub(crate) fn apply(&self, head: u32, cqe: &mut cq::Entry, next: Option<&mut cq::Entry>) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.decided == Some(head) {
+            return;
+        }
+        inner.decided = Some(head);
+        let opcode = if cqe.more() {
+            inner.ops.get(&cqe.user_data()).copied()
+        } else {
+            inner.ops.remove(&cqe.user_data())
+        };
+        let fault = match opcode {
+            Some(opcode) => inner.pick(opcode),
+            None => return,
+        };
+        match fault {
+            Some(Fault::Error(errno)) => cqe.set_res(-errno),
+            Some(Fault::Short) if cqe.res() > 1 => {
+                let res = 1 + inner.next() % (cqe.res() as u64 - 1);
+                cqe.set_res(res as i32);
+            }
+            Some(Fault::Reorder) if next.is_some() => {
+                std::mem::swap(cqe, next.unwrap());
+                // The cqe now at head is another op's, left as it is
+                if !cqe.more() {
+                    inner.ops.remove(&cqe.user_data());
+                }
+            }
+            _ => return,
+        }
+        inner.injected += 1;
+    }
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.borrow();
+        f.debug_struct("FaultInjector")
+            .field("rules", &inner.rules)
+            .field("injected", &inner.injected)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    code: u8,
+    fault: Fault,
+    probability: f64,
+}
+
+struct Inner {
+    state: u64,
+    rules: Vec<Rule>,
+    // Opcodes of the ops in flight, by user data
+    ops: HashMap<u64, u8>,
+    decided: Option<u32>,
+    injected: u64,
+}
+
+impl Inner {
+    fn pick(&mut self, opcode: u8) -> Option<Fault> {
+        for i in 0..self.rules.len() {
+            let rule = &self.rules[i];
+            if rule.code != opcode {
+                continue;
+            }
+            let (fault, probability) = (rule.fault, rule.probability);
+            if self.chance() < probability {
+                return Some(fault);
+            }
+        }
+        None
+    }
+
+    // Uniform in [0, 1)
+    #[inline]
+    fn chance(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // xorshift64*
+    #[inline]
+    fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::cq;
+
+// The state of a ring as the kernel reports it in /proc/<pid>/fdinfo/<fd>,
+// from Uring::fdinfo. Fields the running kernel does not report are None;
+// the ring positions appeared in Linux 5.18.
+#[derive(Debug, Clone, Default)]
+pub struct FdInfo {
+    pub sq_mask: Option<u32>,
+    pub sq_head: Option<u32>,
+    pub sq_tail: Option<u32>,
+    // SQ head the kernel has consumed up to
+    pub cached_sq_head: Option<u32>,
+    pub cq_mask: Option<u32>,
+    pub cq_head: Option<u32>,
+    pub cq_tail: Option<u32>,
+    // CQ tail the kernel has posted up to
+    pub cached_cq_tail: Option<u32>,
+    // Sqes submitted and not consumed yet
+    pub sqes: Option<u32>,
+    // Cqes posted and not reaped yet
+    pub cqes: Option<u32>,
+    // Pid of the SQPOLL thread, and the cpu it is bound to
+    pub sq_thread: Option<i32>,
+    pub sq_thread_cpu: Option<i32>,
+    // Files and buffers registered
+    pub user_files: Option<u32>,
+    pub user_bufs: Option<u32>,
+    // Cqes held back because the CQ ring was full
+    pub overflow: Vec<cq::Entry>,
+}
+
+impl FdInfo {
+    // Parses the fdinfo of a ring, skipping what it does not recognize.
+    pub fn parse(text: &str) -> Self {
+        let mut info = Self::default();
+        let mut in_overflow = false;
+        for line in text.lines() {
+            if line.starts_with(char::is_whitespace) {
+                if in_overflow {
+                    info.overflow.extend(parse_overflow(line.trim()));
+                }
+                continue;
+            }
+            in_overflow = false;
+            let (key, value) = match line.find(':') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => continue,
+            };
+            match key {
+                "SqMask" => info.sq_mask = parse_hex(value),
+                "SqHead" => info.sq_head = value.parse().ok(),
+                "SqTail" => info.sq_tail = value.parse().ok(),
+                "CachedSqHead" => info.cached_sq_head = value.parse().ok(),
+                "CqMask" => info.cq_mask = parse_hex(value),
+                "CqHead" => info.cq_head = value.parse().ok(),
+                "CqTail" => info.cq_tail = value.parse().ok(),
+                "CachedCqTail" => info.cached_cq_tail = value.parse().ok(),
+                "SQEs" => info.sqes = value.parse().ok(),
+                "CQEs" => info.cqes = value.parse().ok(),
+                "SqThread" => info.sq_thread = value.parse().ok().filter(|&pid| pid >= 0),
+                "SqThreadCpu" => info.sq_thread_cpu = value.parse().ok().filter(|&cpu| cpu >= 0),
+                "UserFiles" => info.user_files = value.parse().ok(),
+                "UserBufs" => info.user_bufs = value.parse().ok(),
+                "CqOverflowList" => in_overflow = true,
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+#[inline]
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+// user_data=%llu, res=%d, flags=%x
+fn parse_overflow(line: &str) -> Option<cq::Entry> {
+    let mut user_data = None;
+    let mut res = None;
+    let mut flags = None;
+    for field in line.split(',') {
+        let (key, value) = match field.find('=') {
+            Some(i) => (field[..i].trim(), field[i + 1..].trim()),
+            None => continue,
+        };
+        match key {
+            "user_data" => user_data = value.parse().ok(),
+            "res" => res = value.parse().ok(),
+            "flags" => flags = u32::from_str_radix(value, 16).ok(),
+            _ => {}
+        }
+    }
+    Some(cq::Entry::new(user_data?, res?, flags.unwrap_or(0)))
+}
@@ -0,0 +1,645 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs::Permissions;
+use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Result};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::driver::cvt;
+use crate::op::{
+    self, AtFlags, FsyncFlags, OpenHow, RenameFlags, StatxMask, UnlinkFlags, XattrFlags,
+};
+use crate::Driver;
+
+// A file whose operations are submitted to the ring of a Driver.
+//
+// Reads and writes take ownership of their buffers and hand them back with
+// the result, since the kernel may still use them after the future is
+// dropped. The file is closed with op::Close when dropped, or explicitly
+// with `close` to see the result.
+pub struct File {
+    driver: Driver,
+    // -1 once closed or released by into_raw_fd
+    fd: RawFd,
+}
+
+impl File {
+    // Opens `path` read-only.
+    #[inline]
+    pub async fn open<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<Self> {
+        let how = OpenHow::new(libc::O_RDONLY | libc::O_CLOEXEC, 0);
+        Self::open_with(driver, path, how).await
+    }
+
+    // Opens `path` write-only, creating it if needed and truncating it
+    // otherwise.
+    #[inline]
+    pub async fn create<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<Self> {
+        let how = OpenHow::new(
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+            0o666,
+        );
+        Self::open_with(driver, path, how).await
+    }
+
+    // Opens `path` relative to the current directory with op::Openat2.
+    #[inline]
+    pub async fn open_with<P: AsRef<Path>>(driver: &Driver, path: P, how: OpenHow) -> Result<Self> {
+        Self::open_at(driver, libc::AT_FDCWD, path, how).await
+    }
+
+    // Opens `path` relative to the directory `dfd`, which `how` may confine
+    // the resolution to, see op::ResolveFlags.
+    pub async fn open_at<P: AsRef<Path>>(
+        driver: &Driver,
+        dfd: RawFd,
+        path: P,
+        how: OpenHow,
+    ) -> Result<Self> {
+        let path = cstr(path.as_ref())?;
+        let how = Box::new(how);
+        let op = op::Openat2 {
+            dfd,
+            path: unsafe { &*(path.as_c_str() as *const _) },
+            how: unsafe { &*(&*how as *const _) },
+        };
+        let (cqe, _) = unsafe { driver.submit(op, (path, how)) }.await;
+        let fd = cvt(&cqe)?;
+        Ok(Self {
+            driver: driver.clone(),
+            fd: fd as RawFd,
+        })
+    }
+
+    #[inline]
+    pub fn from_std<T: IntoRawFd>(driver: &Driver, file: T) -> Self {
+        Self {
+            driver: driver.clone(),
+            fd: file.into_raw_fd(),
+        }
+    }
+
+    // Reads up to `buf.len()` bytes at `offset`.
+    pub async fn read_at(&self, mut buf: Vec<u8>, offset: u64) -> (Result<usize>, Vec<u8>) {
+        let op = op::Read {
+            fd: self.fd,
+            buf: unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) },
+            offset,
+        };
+        let (cqe, buf) = unsafe { self.driver.submit(op, buf) }.await;
+        (cvt(&cqe).map(|n| n as usize), buf)
+    }
+
+    // Writes up to `buf.len()` bytes at `offset`.
+    pub async fn write_at(&self, buf: Vec<u8>, offset: u64) -> (Result<usize>, Vec<u8>) {
+        let op = op::Write {
+            fd: self.fd,
+            data: unsafe { std::slice::from_raw_parts(buf.as_ptr(), buf.len()) },
+            offset,
+        };
+        let (cqe, buf) = unsafe { self.driver.submit(op, buf) }.await;
+        (cvt(&cqe).map(|n| n as usize), buf)
+    }
+
+    // Reads at `offset` into `bufs` in order.
+    pub async fn readv_at(
+        &self,
+        mut bufs: Vec<Vec<u8>>,
+        offset: u64,
+    ) -> (Result<usize>, Vec<Vec<u8>>) {
+        let mut iovecs: Vec<IoSliceMut<'static>> = bufs
+            .iter_mut()
+            .map(|buf| {
+                IoSliceMut::new(unsafe {
+                    std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len())
+                })
+            })
+            .collect();
+        let op = op::Readv {
+            fd: self.fd,
+            iovecs: unsafe { std::slice::from_raw_parts_mut(iovecs.as_mut_ptr(), iovecs.len()) },
+            offset,
+        };
+        let (cqe, (bufs, _)) = unsafe { self.driver.submit(op, (bufs, iovecs)) }.await;
+        (cvt(&cqe).map(|n| n as usize), bufs)
+    }
+
+    // Writes `bufs` in order at `offset`.
+    pub async fn writev_at(
+        &self,
+        bufs: Vec<Vec<u8>>,
+        offset: u64,
+    ) -> (Result<usize>, Vec<Vec<u8>>) {
+        let iovecs: Vec<IoSlice<'static>> = bufs
+            .iter()
+            .map(|buf| IoSlice::new(unsafe { std::slice::from_raw_parts(buf.as_ptr(), buf.len()) }))
+            .collect();
+        let op = op::Writev {
+            fd: self.fd,
+            iovecs: unsafe { std::slice::from_raw_parts(iovecs.as_ptr(), iovecs.len()) },
+            offset,
+        };
+        let (cqe, (bufs, _)) = unsafe { self.driver.submit(op, (bufs, iovecs)) }.await;
+        (cvt(&cqe).map(|n| n as usize), bufs)
+    }
+
+    // Flushes data and metadata, as fsync(2).
+    #[inline]
+    pub async fn sync_all(&self) -> Result<()> {
+        self.fsync(FsyncFlags::empty()).await
+    }
+
+    // Flushes data and only the metadata needed to read it back, as
+    // fdatasync(2).
+    #[inline]
+    pub async fn sync_data(&self) -> Result<()> {
+        self.fsync(FsyncFlags::DATASYNC).await
+    }
+
+    #[inline]
+    async fn fsync(&self, flags: FsyncFlags) -> Result<()> {
+        let op = op::Fsync { fd: self.fd, flags };
+        self.submit(op).await
+    }
+
+    // See sync_file_range(2) for `flags`, SYNC_FILE_RANGE_*.
+    #[inline]
+    pub async fn sync_range(&self, offset: u64, len: u32, flags: u32) -> Result<()> {
+        let op = op::SyncFileRange {
+            fd: self.fd,
+            offset,
+            len,
+            flags,
+        };
+        self.submit(op).await
+    }
+
+    // See fallocate(2) for `mode`, FALLOC_FL_*; 0 allocates and extends the
+    // file as needed.
+    #[inline]
+    pub async fn allocate(&self, offset: u64, len: u64, mode: u32) -> Result<()> {
+        let op = op::Fallocate {
+            fd: self.fd,
+            mode,
+            offset,
+            len,
+        };
+        self.submit(op).await
+    }
+
+    // See posix_fadvise(2) for `advice`, POSIX_FADV_*.
+    #[inline]
+    pub async fn advise(&self, offset: u64, len: u32, advice: i32) -> Result<()> {
+        let op = op::Fadvise {
+            fd: self.fd,
+            offset,
+            len,
+            advice,
+        };
+        self.submit(op).await
+    }
+
+    pub async fn metadata(&self) -> Result<Metadata> {
+        let op = op::Statx::fd(self.fd, StatxMask::BASIC_STATS | StatxMask::BTIME);
+        statx(&self.driver, op).await
+    }
+
+    // Gets the extended attribute `name`, such as "user.checksum".
+    pub async fn get_xattr(&self, name: &str) -> Result<Vec<u8>> {
+        let op = op::FGetXattr {
+            fd: self.fd,
+            name: xattr_name(name)?,
+            value: vec![0; XATTR_SIZE],
+        };
+        get_xattr_with(&self.driver, op, |op| &mut op.value).await
+    }
+
+    // Sets the extended attribute `name` to `value`.
+    pub async fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<()> {
+        let op = op::FSetXattr {
+            fd: self.fd,
+            name: xattr_name(name)?,
+            value: value.to_vec(),
+            flags,
+        };
+        set_xattr_with(&self.driver, op).await
+    }
+
+    // Closes the file, reporting errors that dropping it would ignore.
+    pub async fn close(mut self) -> Result<()> {
+        let op = op::Close {
+            fd: mem::replace(&mut self.fd, -1),
+        };
+        let (cqe, ()) = unsafe { self.driver.submit(op, ()) }.await;
+        cvt(&cqe).map(drop)
+    }
+
+    #[inline]
+    async fn submit<T: op::Op>(&self, op: T) -> Result<()> {
+        let (cqe, ()) = unsafe { self.driver.submit(op, ()) }.await;
+        cvt(&cqe).map(drop)
+    }
+}
+
+impl AsRawFd for File {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for File {
+    #[inline]
+    fn into_raw_fd(mut self) -> RawFd {
+        mem::replace(&mut self.fd, -1)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if self.fd < 0 {
+            return;
+        }
+        self.driver.close(self.fd);
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File").field("fd", &self.fd).finish()
+    }
+}
+
+// The result of statx(2) on a file.
+#[derive(Copy, Clone)]
+pub struct Metadata(libc::statx);
+
+impl Metadata {
+    #[inline]
+    pub fn from_raw(statx: libc::statx) -> Self {
+        Self(statx)
+    }
+
+    // The fields the kernel filled, which may differ from those asked for
+    #[inline]
+    pub fn mask(&self) -> StatxMask {
+        StatxMask::from_bits_truncate(self.0.stx_mask)
+    }
+
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.0.stx_size
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.stx_size == 0
+    }
+
+    #[inline]
+    pub fn file_type(&self) -> FileType {
+        FileType(self.0.stx_mode as u32 & libc::S_IFMT)
+    }
+
+    #[inline]
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    #[inline]
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from_mode(self.mode())
+    }
+
+    // Permission bits, as st_mode & 0o7777
+    #[inline]
+    pub fn mode(&self) -> u32 {
+        self.0.stx_mode as u32 & 0o7777
+    }
+
+    #[inline]
+    pub fn uid(&self) -> u32 {
+        self.0.stx_uid
+    }
+
+    #[inline]
+    pub fn gid(&self) -> u32 {
+        self.0.stx_gid
+    }
+
+    #[inline]
+    pub fn nlink(&self) -> u32 {
+        self.0.stx_nlink
+    }
+
+    #[inline]
+    pub fn ino(&self) -> u64 {
+        self.0.stx_ino
+    }
+
+    // The device the file is on, as st_dev
+    #[inline]
+    pub fn dev(&self) -> u64 {
+        libc::makedev(self.0.stx_dev_major, self.0.stx_dev_minor)
+    }
+
+    // Number of 512-byte blocks allocated
+    #[inline]
+    pub fn blocks(&self) -> u64 {
+        self.0.stx_blocks
+    }
+
+    #[inline]
+    pub fn blksize(&self) -> u32 {
+        self.0.stx_blksize
+    }
+
+    #[inline]
+    pub fn accessed(&self) -> SystemTime {
+        system_time(self.0.stx_atime)
+    }
+
+    #[inline]
+    pub fn modified(&self) -> SystemTime {
+        system_time(self.0.stx_mtime)
+    }
+
+    // Last status change, as st_ctime
+    #[inline]
+    pub fn changed(&self) -> SystemTime {
+        system_time(self.0.stx_ctime)
+    }
+
+    // Creation time, if asked for with StatxMask::BTIME and supported by the
+    // filesystem
+    #[inline]
+    pub fn created(&self) -> Option<SystemTime> {
+        self.filled(StatxMask::BTIME)
+            .then(|| system_time(self.0.stx_btime))
+    }
+
+    // If asked for with StatxMask::MNT_ID
+    #[inline]
+    pub fn mount_id(&self) -> Option<u64> {
+        self.filled(StatxMask::MNT_ID).then_some(self.0.stx_mnt_id)
+    }
+
+    #[inline]
+    pub fn as_raw(&self) -> &libc::statx {
+        &self.0
+    }
+
+    #[inline]
+    fn filled(&self, mask: StatxMask) -> bool {
+        self.mask().contains(mask)
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metadata")
+            .field("file_type", &self.file_type())
+            .field("len", &self.len())
+            .field("mode", &format_args!("{:o}", self.mode()))
+            .field("modified", &self.modified())
+            .finish()
+    }
+}
+
+// The type of a file, as st_mode & S_IFMT.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FileType(u32);
+
+impl FileType {
+    #[inline]
+    pub fn is_file(&self) -> bool {
+        self.0 == libc::S_IFREG
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.0 == libc::S_IFDIR
+    }
+
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        self.0 == libc::S_IFLNK
+    }
+
+    #[inline]
+    pub fn is_block_device(&self) -> bool {
+        self.0 == libc::S_IFBLK
+    }
+
+    #[inline]
+    pub fn is_char_device(&self) -> bool {
+        self.0 == libc::S_IFCHR
+    }
+
+    #[inline]
+    pub fn is_fifo(&self) -> bool {
+        self.0 == libc::S_IFIFO
+    }
+
+    #[inline]
+    pub fn is_socket(&self) -> bool {
+        self.0 == libc::S_IFSOCK
+    }
+}
+
+impl fmt::Debug for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            libc::S_IFREG => "File",
+            libc::S_IFDIR => "Dir",
+            libc::S_IFLNK => "Symlink",
+            libc::S_IFBLK => "BlockDevice",
+            libc::S_IFCHR => "CharDevice",
+            libc::S_IFIFO => "Fifo",
+            libc::S_IFSOCK => "Socket",
+            _ => return write!(f, "FileType({:#o})", self.0),
+        };
+        f.write_str(name)
+    }
+}
+
+#[inline]
+fn system_time(ts: libc::statx_timestamp) -> SystemTime {
+    let nsec = Duration::from_nanos(ts.tv_nsec as u64);
+    if ts.tv_sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(ts.tv_sec as u64) + nsec
+    } else {
+        UNIX_EPOCH - Duration::from_secs(ts.tv_sec.unsigned_abs()) + nsec
+    }
+}
+
+// Gets the metadata of the file at `path`, following symlinks.
+#[inline]
+pub async fn metadata<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<Metadata> {
+    let op = op::Statx::new(
+        libc::AT_FDCWD,
+        cstr(path.as_ref())?,
+        AtFlags::empty(),
+        StatxMask::BASIC_STATS | StatxMask::BTIME,
+    );
+    statx(driver, op).await
+}
+
+// Gets the metadata of the file at `path`, without following symlinks.
+#[inline]
+pub async fn symlink_metadata<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<Metadata> {
+    let op = op::Statx::new(
+        libc::AT_FDCWD,
+        cstr(path.as_ref())?,
+        AtFlags::SYMLINK_NOFOLLOW,
+        StatxMask::BASIC_STATS | StatxMask::BTIME,
+    );
+    statx(driver, op).await
+}
+
+#[inline]
+async fn statx(driver: &Driver, op: op::Statx) -> Result<Metadata> {
+    let (cqe, op) = unsafe { driver.submit_owned(op) }.await;
+    cvt(&cqe)?;
+    Ok(Metadata::from_raw(*op.statx()))
+}
+
+// Renames `from` to `to`, replacing `to` if it exists, as rename(2).
+pub async fn rename<P: AsRef<Path>, Q: AsRef<Path>>(driver: &Driver, from: P, to: Q) -> Result<()> {
+    let from = cstr(from.as_ref())?;
+    let to = cstr(to.as_ref())?;
+    let op = op::RenameAt {
+        old_dfd: libc::AT_FDCWD,
+        old_path: unsafe { &*(from.as_c_str() as *const _) },
+        new_dfd: libc::AT_FDCWD,
+        new_path: unsafe { &*(to.as_c_str() as *const _) },
+        flags: RenameFlags::empty(),
+    };
+    let (cqe, _) = unsafe { driver.submit(op, (from, to)) }.await;
+    cvt(&cqe).map(drop)
+}
+
+// Removes the file or symlink at `path`, as unlink(2).
+pub async fn remove_file<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<()> {
+    let path = cstr(path.as_ref())?;
+    let op = op::UnlinkAt {
+        dfd: libc::AT_FDCWD,
+        path: unsafe { &*(path.as_c_str() as *const _) },
+        flags: UnlinkFlags::empty(),
+    };
+    let (cqe, _) = unsafe { driver.submit(op, path) }.await;
+    cvt(&cqe).map(drop)
+}
+
+// Creates an empty directory at `path` with mode 0o777 less the umask.
+pub async fn create_dir<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<()> {
+    let path = cstr(path.as_ref())?;
+    let op = op::MkdirAt {
+        dfd: libc::AT_FDCWD,
+        path: unsafe { &*(path.as_c_str() as *const _) },
+        mode: 0o777,
+    };
+    let (cqe, _) = unsafe { driver.submit(op, path) }.await;
+    cvt(&cqe).map(drop)
+}
+
+// Gets the extended attribute `name` of the file at `path`, following
+// symlinks.
+pub async fn get_xattr<P: AsRef<Path>>(driver: &Driver, path: P, name: &str) -> Result<Vec<u8>> {
+    let op = op::GetXattr {
+        path: cstr(path.as_ref())?,
+        name: xattr_name(name)?,
+        value: vec![0; XATTR_SIZE],
+    };
+    get_xattr_with(driver, op, |op| &mut op.value).await
+}
+
+// Sets the extended attribute `name` of the file at `path` to `value`,
+// following symlinks.
+pub async fn set_xattr<P: AsRef<Path>>(
+    driver: &Driver,
+    path: P,
+    name: &str,
+    value: &[u8],
+    flags: XattrFlags,
+) -> Result<()> {
+    let op = op::SetXattr {
+        path: cstr(path.as_ref())?,
+        name: xattr_name(name)?,
+        value: value.to_vec(),
+        flags,
+    };
+    set_xattr_with(driver, op).await
+}
+
+// Initial buffer for xattr values, doubled on ERANGE up to XATTR_SIZE_MAX
+const XATTR_SIZE: usize = 256;
+const XATTR_SIZE_MAX: usize = 64 * 1024;
+
+async fn get_xattr_with<T, F>(driver: &Driver, mut op: T, value: F) -> Result<Vec<u8>>
+where
+    T: op::Op + 'static,
+    F: Fn(&mut T) -> &mut Vec<u8>,
+{
+    supported::<T>(driver)?;
+    loop {
+        let (cqe, mut done) = unsafe { driver.submit_owned(op) }.await;
+        let buf = value(&mut done);
+        match cvt(&cqe) {
+            Ok(n) => {
+                let mut buf = mem::take(buf);
+                buf.truncate(n as usize);
+                return Ok(buf);
+            }
+            Err(err) if err.raw_os_error() == Some(libc::ERANGE) && buf.len() < XATTR_SIZE_MAX => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            Err(err) => return Err(err),
+        }
+        op = done;
+    }
+}
+
+async fn set_xattr_with<T: op::Op + 'static>(driver: &Driver, op: T) -> Result<()> {
+    supported::<T>(driver)?;
+    let (cqe, _) = unsafe { driver.submit_owned(op) }.await;
+    cvt(&cqe).map(drop)
+}
+
+#[inline]
+fn supported<T: op::Op>(driver: &Driver) -> Result<()> {
+    if driver.supports::<T>() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "op not supported by the kernel",
+        ))
+    }
+}
+
+#[inline]
+fn xattr_name(name: &str) -> Result<CString> {
+    CString::new(name).map_err(|_| Error::new(ErrorKind::InvalidInput, "name contains a nul byte"))
+}
+
+#[inline]
+fn cstr(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a nul byte"))
+}
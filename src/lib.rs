@@ -1,10 +1,47 @@
 pub mod cq;
+pub mod fs;
+pub mod net;
 pub mod op;
+pub mod pipe;
 pub mod sq;
 
+#[cfg(feature = "tokio")]
+pub mod compat;
+
+#[cfg(feature = "fault-injection")]
+mod fault;
+#[cfg(feature = "simulator")]
+mod sim;
+#[cfg(feature = "tracing")]
+mod trace;
+
+mod caps;
+mod driver;
+mod family;
+mod fdinfo;
+mod msg;
+mod notify;
 mod params;
+mod stats;
 mod sys;
+mod timer;
+mod udata;
 mod uring;
 
-pub use params::UringBuilder;
-pub use uring::{Restriction, Uring};
+pub use caps::Capabilities;
+pub use driver::{Completion, Driver, Multishot};
+pub use family::WqFamily;
+#[cfg(feature = "fault-injection")]
+pub use fault::{Fault, FaultInjector};
+pub use fdinfo::FdInfo;
+pub use msg::{Cmsg, MsgHdr, RecvMsgOut};
+pub use notify::EventfdNotifier;
+pub use params::{Feat, Setup, UringBuilder};
+#[cfg(feature = "simulator")]
+pub use sim::Simulator;
+pub use stats::Stats;
+pub use timer::{TimerKey, TimerWheel};
+#[cfg(feature = "tracing")]
+pub use trace::Histogram;
+pub use udata::{Dispatcher, Handler, UserData};
+pub use uring::{Probe, Restriction, RingHandle, Uring};
@@ -0,0 +1,476 @@
+use std::cmp;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::Result;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use crate::net::SockAddr;
+
+// A control message, as encoded by MsgHdr for sending and decoded from a
+// received MsgHdr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cmsg {
+    // SCM_RIGHTS, file descriptors passed over a unix socket. Received fds
+    // are owned by the caller.
+    Rights(Vec<RawFd>),
+    // SO_TIMESTAMPING: SOF_TIMESTAMPING_* flags when sending, the software,
+    // legacy and raw hardware timestamps when receiving
+    TimestampingFlags(u32),
+    Timestamping([Duration; 3]),
+    // UDP_SEGMENT, the GSO segment size to split a send into
+    UdpSegment(u16),
+    // UDP_GRO, the size of the segments coalesced into a receive
+    UdpGro(u16),
+    Other { level: i32, ty: i32, data: Vec<u8> },
+}
+
+impl Cmsg {
+    // Control buffer space taken by a message carrying `len` bytes of data.
+    #[inline]
+    pub fn space(len: usize) -> usize {
+        unsafe { libc::CMSG_SPACE(len as u32) as usize }
+    }
+
+    // Control buffer space for receiving `n` fds.
+    #[inline]
+    pub fn space_for_rights(n: usize) -> usize {
+        Self::space(n * mem::size_of::<RawFd>())
+    }
+
+    // Control buffer space for receiving SO_TIMESTAMPING.
+    #[inline]
+    pub fn space_for_timestamping() -> usize {
+        Self::space(3 * mem::size_of::<libc::timespec>())
+    }
+
+    #[inline]
+    fn encode(&self) -> (i32, i32, Vec<u8>) {
+        match self {
+            Cmsg::Rights(fds) => (
+                libc::SOL_SOCKET,
+                libc::SCM_RIGHTS,
+                fds.iter().flat_map(|fd| fd.to_ne_bytes()).collect(),
+            ),
+            Cmsg::TimestampingFlags(flags) => (
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPING,
+                flags.to_ne_bytes().to_vec(),
+            ),
+            Cmsg::Timestamping(ts) => (
+                libc::SOL_SOCKET,
+                libc::SCM_TIMESTAMPING,
+                ts.iter()
+                    .flat_map(|ts| as_bytes(&crate::op::timespec(*ts)).to_vec())
+                    .collect(),
+            ),
+            Cmsg::UdpSegment(size) => (
+                libc::SOL_UDP,
+                libc::UDP_SEGMENT,
+                size.to_ne_bytes().to_vec(),
+            ),
+            Cmsg::UdpGro(size) => (
+                libc::SOL_UDP,
+                libc::UDP_GRO,
+                (*size as i32).to_ne_bytes().to_vec(),
+            ),
+            Cmsg::Other { level, ty, data } => (*level, *ty, data.clone()),
+        }
+    }
+
+    fn decode(level: i32, ty: i32, data: &[u8]) -> Self {
+        match (level, ty) {
+            (libc::SOL_SOCKET, libc::SCM_RIGHTS) => Cmsg::Rights(
+                data.chunks_exact(mem::size_of::<RawFd>())
+                    .map(|b| RawFd::from_ne_bytes(b.try_into().unwrap()))
+                    .collect(),
+            ),
+            (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING)
+                if data.len() >= 3 * mem::size_of::<libc::timespec>() =>
+            {
+                let ts = data.as_ptr() as *const libc::timespec;
+                let ts = [0, 1, 2].map(|i| {
+                    let ts = unsafe { ptr::read_unaligned(ts.add(i)) };
+                    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+                });
+                Cmsg::Timestamping(ts)
+            }
+            (libc::SOL_UDP, libc::UDP_GRO) if data.len() >= 4 => {
+                Cmsg::UdpGro(i32::from_ne_bytes(data[..4].try_into().unwrap()) as u16)
+            }
+            (libc::SOL_UDP, libc::UDP_SEGMENT) if data.len() >= 2 => {
+                Cmsg::UdpSegment(u16::from_ne_bytes(data[..2].try_into().unwrap()))
+            }
+            _ => Cmsg::Other {
+                level,
+                ty,
+                data: data.to_vec(),
+            },
+        }
+    }
+}
+
+fn decode_cmsgs(hdr: &libc::msghdr) -> Vec<Cmsg> {
+    let mut cmsgs = Vec::new();
+    if hdr.msg_control.is_null() {
+        return cmsgs;
+    }
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+            let data = slice::from_raw_parts(data, len);
+            cmsgs.push(Cmsg::decode((*cmsg).cmsg_level, (*cmsg).cmsg_type, data));
+            cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+        }
+    }
+    cmsgs
+}
+
+#[inline]
+fn as_bytes<T>(val: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(val as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+struct Inner {
+    hdr: libc::msghdr,
+    name: Option<SockAddr>,
+    // Whether name is filled by receives
+    recv_name: bool,
+    bufs: Vec<Vec<u8>>,
+    iovecs: Vec<libc::iovec>,
+    // Aligned for cmsghdr
+    control: Vec<u64>,
+    control_len: usize,
+}
+
+// A msghdr that owns its buffers, for op::SendMsg and op::RecvMsg.
+//
+// Everything the msghdr points to lives in one heap allocation together
+// with the buffers, so the MsgHdr can be moved into the data of an op and
+// handed back with its completion, see net::UdpSocket::send_msg.
+pub struct MsgHdr {
+    inner: Box<Inner>,
+}
+
+impl MsgHdr {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            inner: Box::new(Inner {
+                hdr: unsafe { mem::zeroed() },
+                name: None,
+                recv_name: false,
+                bufs: Vec::new(),
+                iovecs: Vec::new(),
+                control: Vec::new(),
+                control_len: 0,
+            }),
+        }
+    }
+
+    // Adds a buffer, sent in full or received into up to its length.
+    #[inline]
+    pub fn buf(mut self, buf: Vec<u8>) -> Self {
+        self.inner.bufs.push(buf);
+        self
+    }
+
+    // Sets the destination address of a send.
+    #[inline]
+    pub fn name(mut self, addr: SocketAddr) -> Self {
+        self.inner.name = Some(SockAddr::from_inet(&addr));
+        self.inner.recv_name = false;
+        self
+    }
+
+    // Makes room for the source address of a receive.
+    #[inline]
+    pub fn name_space(mut self) -> Self {
+        self.inner.name = Some(SockAddr::empty());
+        self.inner.recv_name = true;
+        self
+    }
+
+    // Appends a control message to send.
+    pub fn cmsg(mut self, cmsg: Cmsg) -> Self {
+        let (level, ty, data) = cmsg.encode();
+        let offset = self.inner.control_len;
+        self.reserve(Cmsg::space(data.len()));
+        unsafe {
+            let base = self.inner.control.as_mut_ptr() as *mut u8;
+            let hdr = base.add(offset) as *mut libc::cmsghdr;
+            (*hdr).cmsg_level = level;
+            (*hdr).cmsg_type = ty;
+            (*hdr).cmsg_len = libc::CMSG_LEN(data.len() as u32) as _;
+            let dst = libc::CMSG_DATA(hdr);
+            ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len());
+        }
+        self
+    }
+
+    // Makes room for `len` bytes of received control messages, see
+    // Cmsg::space.
+    #[inline]
+    pub fn control_space(mut self, len: usize) -> Self {
+        self.reserve(len);
+        self
+    }
+
+    #[inline]
+    fn reserve(&mut self, len: usize) {
+        let inner = &mut *self.inner;
+        inner.control_len += len;
+        let words = inner.control_len.div_ceil(mem::size_of::<u64>());
+        inner.control.resize(words, 0);
+    }
+
+    // Points the msghdr at the buffers and resets what a previous receive
+    // changed. To be called before each submission.
+    pub fn as_raw_mut(&mut self) -> &mut libc::msghdr {
+        let inner = &mut *self.inner;
+        inner.iovecs = inner
+            .bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut _,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let hdr = &mut inner.hdr;
+        match inner.name.as_mut() {
+            Some(name) => {
+                if inner.recv_name {
+                    *name = SockAddr::empty();
+                }
+                hdr.msg_name = name.as_mut_ptr() as *mut _;
+                hdr.msg_namelen = name.len();
+            }
+            None => {
+                hdr.msg_name = ptr::null_mut();
+                hdr.msg_namelen = 0;
+            }
+        }
+        hdr.msg_iov = inner.iovecs.as_mut_ptr();
+        hdr.msg_iovlen = inner.iovecs.len() as _;
+        if inner.control_len > 0 {
+            hdr.msg_control = inner.control.as_mut_ptr() as *mut _;
+            hdr.msg_controllen = inner.control_len as _;
+        } else {
+            hdr.msg_control = ptr::null_mut();
+            hdr.msg_controllen = 0;
+        }
+        hdr.msg_flags = 0;
+        hdr
+    }
+
+    // The msghdr as the kernel left it after a receive.
+    #[inline]
+    pub fn as_raw(&self) -> &libc::msghdr {
+        &self.inner.hdr
+    }
+
+    // Source address of a received message.
+    pub fn source(&self) -> Option<Result<SocketAddr>> {
+        let name = self.inner.name.as_ref()?;
+        let mut addr = *name;
+        *addr.len_mut() = self.inner.hdr.msg_namelen;
+        Some(addr.inet())
+    }
+
+    // MSG_* flags of a received message, such as MSG_TRUNC and MSG_CTRUNC.
+    #[inline]
+    pub fn flags(&self) -> i32 {
+        self.inner.hdr.msg_flags
+    }
+
+    // Control messages of a received message.
+    #[inline]
+    pub fn cmsgs(&self) -> Vec<Cmsg> {
+        decode_cmsgs(&self.inner.hdr)
+    }
+
+    #[inline]
+    pub fn bufs(&self) -> &[Vec<u8>] {
+        &self.inner.bufs
+    }
+
+    #[inline]
+    pub fn into_bufs(self) -> Vec<Vec<u8>> {
+        self.inner.bufs
+    }
+}
+
+impl Default for MsgHdr {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MsgHdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MsgHdr")
+            .field("name", &self.inner.name)
+            .field("bufs", &self.inner.bufs.len())
+            .field("control_len", &self.inner.control_len)
+            .field("flags", &self.inner.hdr.msg_flags)
+            .finish()
+    }
+}
+
+// struct io_uring_recvmsg_out
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct RecvMsgHeader {
+    namelen: u32,
+    controllen: u32,
+    payloadlen: u32,
+    flags: u32,
+}
+
+// A view of a buffer filled by op::RecvMsgMulti, found with
+// cq::Entry::buffer_id.
+#[derive(Debug, Copy, Clone)]
+pub struct RecvMsgOut<'a> {
+    header: RecvMsgHeader,
+    name: &'a [u8],
+    control: &'a [u8],
+    payload: &'a [u8],
+}
+
+impl<'a> RecvMsgOut<'a> {
+    // Parses the first `cqe.res()` bytes of the buffer, laid out after `msg`,
+    // the msghdr the op was submitted with. Returns None if `buf` is too
+    // short to hold the layout.
+    pub fn parse(buf: &'a [u8], msg: &libc::msghdr) -> Option<Self> {
+        let header_len = mem::size_of::<RecvMsgHeader>();
+        let name_len = msg.msg_namelen as usize;
+        #[allow(clippy::unnecessary_cast)] // u32 on musl
+        let control_len = msg.msg_controllen as usize;
+        let payload_start = header_len + name_len + control_len;
+        if buf.len() < payload_start {
+            return None;
+        }
+        let header = unsafe { ptr::read_unaligned(buf.as_ptr() as *const RecvMsgHeader) };
+        let name_end = header_len + cmp::min(header.namelen as usize, name_len);
+        let control_start = header_len + name_len;
+        let control_end = control_start + cmp::min(header.controllen as usize, control_len);
+        let payload_end = cmp::min(payload_start + header.payloadlen as usize, buf.len());
+        Some(Self {
+            header,
+            name: &buf[header_len..name_end],
+            control: &buf[control_start..control_end],
+            payload: &buf[payload_start..payload_end],
+        })
+    }
+
+    // Source address, None if no room was made for it or the socket is
+    // connected.
+    pub fn source(&self) -> Option<Result<SocketAddr>> {
+        if self.name.is_empty() {
+            return None;
+        }
+        Some(SockAddr::from_bytes(self.name).inet())
+    }
+
+    pub fn cmsgs(&self) -> Vec<Cmsg> {
+        // Copied for the alignment of cmsghdr
+        let mut control = vec![0u64; self.control.len().div_ceil(mem::size_of::<u64>())];
+        unsafe {
+            let dst = control.as_mut_ptr() as *mut u8;
+            ptr::copy_nonoverlapping(self.control.as_ptr(), dst, self.control.len());
+        }
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+        if !self.control.is_empty() {
+            hdr.msg_control = control.as_mut_ptr() as *mut _;
+            hdr.msg_controllen = self.control.len() as _;
+        }
+        decode_cmsgs(&hdr)
+    }
+
+    // The payload received, cut short if it did not fit in the buffer.
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    // Length of the payload received; of the whole datagram, even if
+    // truncated, when the op was submitted with MSG_TRUNC.
+    #[inline]
+    pub fn payload_len(&self) -> usize {
+        self.header.payloadlen as usize
+    }
+
+    #[inline]
+    pub fn flags(&self) -> i32 {
+        self.header.flags as i32
+    }
+
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.flags() & libc::MSG_TRUNC != 0 || self.payload.len() < self.payload_len()
+    }
+
+    #[inline]
+    pub fn is_control_truncated(&self) -> bool {
+        self.flags() & libc::MSG_CTRUNC != 0
+    }
+
+    #[inline]
+    pub fn is_name_truncated(&self) -> bool {
+        self.header.namelen as usize > self.name.len()
+    }
+}
+
+// Lays out a message received by op::RecvMsgSelect as op::RecvMsgMulti
+// would have: the first `len` bytes of `buf`, the payload, are moved behind
+// room for the header, name and control messages of `layout`, which are
+// filled from `received`. Returns the length of the buffer laid out, the
+// payload cut short if it no longer fits.
+pub(crate) fn lay_out(
+    buf: &mut [u8],
+    len: usize,
+    layout: &libc::msghdr,
+    received: &libc::msghdr,
+) -> usize {
+    let header_len = mem::size_of::<RecvMsgHeader>();
+    let name_len = layout.msg_namelen as usize;
+    #[allow(clippy::unnecessary_cast)] // u32 on musl
+    let control_len = layout.msg_controllen as usize;
+    let payload_start = header_len + name_len + control_len;
+    if buf.len() < payload_start {
+        return 0;
+    }
+    let copied = cmp::min(len, buf.len() - payload_start);
+    buf.copy_within(..copied, payload_start);
+    let mut flags = received.msg_flags as u32;
+    if copied < len {
+        flags |= libc::MSG_TRUNC as u32;
+    }
+    let header = RecvMsgHeader {
+        namelen: received.msg_namelen,
+        controllen: received.msg_controllen as u32,
+        payloadlen: len as u32,
+        flags,
+    };
+    unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut RecvMsgHeader, header) };
+    let name = cmp::min(received.msg_namelen as usize, name_len);
+    if name > 0 {
+        let src = unsafe { slice::from_raw_parts(received.msg_name as *const u8, name) };
+        buf[header_len..header_len + name].copy_from_slice(src);
+    }
+    #[allow(clippy::unnecessary_cast)] // u32 on musl
+    let control = cmp::min(received.msg_controllen as usize, control_len);
+    if control > 0 {
+        let src = unsafe { slice::from_raw_parts(received.msg_control as *const u8, control) };
+        let start = header_len + name_len;
+        buf[start..start + control].copy_from_slice(src);
+    }
+    payload_start + copied
+}
@@ -0,0 +1,1130 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+use crate::driver::cvt;
+use crate::msg;
+use crate::op;
+use crate::{sys, Driver, MsgHdr, Multishot, RecvMsgOut};
+
+const BACKLOG: i32 = 1024;
+
+// A socket address in the form the kernel takes and fills.
+#[derive(Copy, Clone)]
+pub(crate) struct SockAddr {
+    storage: libc::sockaddr_storage,
+    len: libc::socklen_t,
+}
+
+impl SockAddr {
+    // Room for any address, to be filled by the kernel
+    #[inline]
+    pub fn empty() -> Self {
+        Self {
+            storage: unsafe { mem::zeroed() },
+            len: mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+        }
+    }
+
+    pub fn from_inet(addr: &SocketAddr) -> Self {
+        let mut sa = Self::empty();
+        match addr {
+            SocketAddr::V4(addr) => {
+                let sin = unsafe { &mut *(&mut sa.storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                sa.len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            }
+            SocketAddr::V6(addr) => {
+                let sin6 = unsafe { &mut *(&mut sa.storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+                sa.len = mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+            }
+        }
+        sa
+    }
+
+    // An address copied from the name area of a received message
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut sa = Self::empty();
+        let len = cmp::min(bytes.len(), mem::size_of::<libc::sockaddr_storage>());
+        unsafe {
+            let dst = &mut sa.storage as *mut _ as *mut u8;
+            ptr::copy_nonoverlapping(bytes.as_ptr(), dst, len);
+        }
+        sa.len = len as libc::socklen_t;
+        sa
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let mut sa = Self::empty();
+        let sun = unsafe { &mut *(&mut sa.storage as *mut _ as *mut libc::sockaddr_un) };
+        let bytes = path.as_os_str().as_bytes();
+        if bytes.len() >= sun.sun_path.len() || bytes.contains(&0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid unix socket path",
+            ));
+        }
+        sun.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, &src) in sun.sun_path.iter_mut().zip(bytes) {
+            *dst = src as libc::c_char;
+        }
+        let base = sun.sun_path.as_ptr() as usize - sun as *const _ as usize;
+        sa.len = (base + bytes.len() + 1) as libc::socklen_t;
+        Ok(sa)
+    }
+
+    pub fn inet(&self) -> Result<SocketAddr> {
+        match self.storage.ss_family as i32 {
+            libc::AF_INET => {
+                let sin = unsafe { &*(&self.storage as *const _ as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+                Ok(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).into())
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(&self.storage as *const _ as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                Ok(SocketAddrV6::new(
+                    ip,
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )
+                .into())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, "not an inet address")),
+        }
+    }
+
+    #[inline]
+    pub fn family(&self) -> i32 {
+        self.storage.ss_family as i32
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *const libc::sockaddr {
+        &self.storage as *const _ as *const _
+    }
+
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut libc::sockaddr {
+        &mut self.storage as *mut _ as *mut _
+    }
+
+    #[inline]
+    pub fn len(&self) -> libc::socklen_t {
+        self.len
+    }
+
+    #[inline]
+    pub fn len_mut(&mut self) -> &mut libc::socklen_t {
+        &mut self.len
+    }
+}
+
+impl fmt::Debug for SockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.inet() {
+            Ok(addr) => write!(f, "{}", addr),
+            Err(_) => write!(f, "SockAddr {{ family: {} }}", self.family()),
+        }
+    }
+}
+
+// A pool of equally sized buffers provided to the kernel under a group id,
+// for receives that pick their buffer when data arrives.
+pub struct BufferGroup {
+    driver: Driver,
+    bgid: u16,
+    count: u16,
+    size: usize,
+    // Owned memory of count * size bytes, written by the kernel
+    mem: *mut u8,
+    // Buffers given back whose ProvideBuffers could not be submitted yet
+    unprovided: RefCell<Vec<u16>>,
+}
+
+impl BufferGroup {
+    pub async fn new(driver: &Driver, bgid: u16, count: u16, size: usize) -> Result<Rc<Self>> {
+        let mem = vec![0u8; count as usize * size].into_boxed_slice();
+        let group = Rc::new(Self {
+            driver: driver.clone(),
+            bgid,
+            count,
+            size,
+            mem: Box::into_raw(mem) as *mut u8,
+            unprovided: RefCell::new(Vec::new()),
+        });
+        let op = op::ProvideBuffers {
+            addr: unsafe { slice::from_raw_parts_mut(group.mem, size) },
+            nr: count as i32,
+            bgid,
+            bid: 0,
+        };
+        let (cqe, ()) = unsafe { driver.submit(op, ()) }.await;
+        cvt(&cqe)?;
+        Ok(group)
+    }
+
+    #[inline]
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    #[inline]
+    pub fn buffer_size(&self) -> usize {
+        self.size
+    }
+
+    // Takes the buffer picked for the completion `cqe` of a receive.
+    pub fn take(self: &Rc<Self>, cqe: &crate::cq::Entry) -> Result<GroupBuf> {
+        let len = cvt(cqe)? as usize;
+        Ok(GroupBuf {
+            group: self.clone(),
+            bid: cqe.buffer_id(),
+            len,
+        })
+    }
+
+    // Hands the buffer `bid` back to the kernel, along with those that could
+    // not be handed back before. Those that still cannot, with the SQ ring
+    // full, are kept for the next call.
+    fn recycle(&self, bid: u16) {
+        let mut unprovided = self.unprovided.borrow_mut();
+        unprovided.push(bid);
+        while let Some(bid) = unprovided.pop() {
+            let op = op::ProvideBuffers {
+                addr: unsafe { slice::from_raw_parts_mut(self.buffer(bid), self.size) },
+                nr: 1,
+                bgid: self.bgid,
+                bid: bid as u32,
+            };
+            if unsafe { self.driver.submit(op, ()) }.is_failed() {
+                unprovided.push(bid);
+                break;
+            }
+        }
+    }
+
+    #[inline]
+    fn buffer(&self, bid: u16) -> *mut u8 {
+        unsafe { self.mem.add(bid as usize * self.size) }
+    }
+}
+
+impl Drop for BufferGroup {
+    fn drop(&mut self) {
+        let len = self.count as usize * self.size;
+        let mem = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.mem, len)) };
+        let op = op::RemoveBuffers {
+            nr: self.count as i32,
+            bgid: self.bgid,
+        };
+        // The memory is freed once the kernel has let go of it. Should the
+        // op not reach the kernel, the buffers stay provided and the memory
+        // is leaked.
+        let completion = unsafe { self.driver.submit(op, mem) };
+        if completion.is_failed() {
+            completion.leak_data();
+        }
+    }
+}
+
+impl fmt::Debug for BufferGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferGroup")
+            .field("bgid", &self.bgid)
+            .field("count", &self.count)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+// Data received into a buffer of a BufferGroup, which gets the buffer back
+// on drop.
+pub struct GroupBuf {
+    group: Rc<BufferGroup>,
+    // None if the kernel picked no buffer, at end of stream
+    bid: Option<u16>,
+    len: usize,
+}
+
+impl GroupBuf {
+    #[inline]
+    pub fn buffer_id(&self) -> Option<u16> {
+        self.bid
+    }
+}
+
+impl Deref for GroupBuf {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self.bid {
+            Some(bid) => unsafe { slice::from_raw_parts(self.group.buffer(bid), self.len) },
+            None => &[],
+        }
+    }
+}
+
+impl Drop for GroupBuf {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(bid) = self.bid {
+            self.group.recycle(bid);
+        }
+    }
+}
+
+impl fmt::Debug for GroupBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupBuf")
+            .field("bid", &self.bid)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+// Data received by a multishot receive, see TcpStream::recv_multi. Falls
+// back to single-shot receives on kernels without multishot receive.
+#[derive(Debug)]
+pub struct RecvStream {
+    driver: Driver,
+    fd: RawFd,
+    group: Rc<BufferGroup>,
+    // None once fallen back to single-shot receives
+    multi: Option<Multishot<Rc<BufferGroup>>>,
+    // No cqe received yet
+    first: bool,
+    done: bool,
+}
+
+impl RecvStream {
+    // Returns the next data received, None at end of stream or once the
+    // receive stops, typically for lack of buffers.
+    pub async fn next(&mut self) -> Option<Result<GroupBuf>> {
+        if self.done {
+            return None;
+        }
+        let first = mem::replace(&mut self.first, false);
+        if let Some(multi) = &mut self.multi {
+            match multi.next().await {
+                // 5.19 rejects the multishot flag
+                Some(cqe) if first && cqe.res() == -libc::EINVAL => self.multi = None,
+                Some(cqe) => {
+                    // Earlier kernels ignore it and receive once
+                    if first && !cqe.more() && cqe.res() > 0 {
+                        self.multi = None;
+                    }
+                    if cqe.res() == 0 {
+                        self.done = true;
+                        return None;
+                    }
+                    return Some(self.group.take(&cqe));
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+        let res = recv_select(&self.driver, self.fd, &self.group).await;
+        match &res {
+            Ok(buf) if buf.is_empty() => {
+                self.done = true;
+                return None;
+            }
+            Err(_) => self.done = true,
+            Ok(_) => {}
+        }
+        Some(res)
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+// Messages received by a multishot recvmsg, see UdpSocket::recv_msg_multi.
+// Falls back to single-shot receives on kernels without multishot recvmsg,
+// laying their buffers out the same way.
+#[derive(Debug)]
+pub struct RecvMsgStream {
+    driver: Driver,
+    fd: RawFd,
+    group: Rc<BufferGroup>,
+    // The name and control lengths the buffers are laid out for
+    layout: libc::msghdr,
+    // None once fallen back to single-shot receives
+    multi: Option<Multishot<(Rc<BufferGroup>, Box<SelectHdr>)>>,
+    // No cqe received yet
+    first: bool,
+    done: bool,
+}
+
+impl RecvMsgStream {
+    // Returns the next message, None once the receive stops, typically for
+    // lack of buffers.
+    pub async fn next(&mut self) -> Option<Result<RecvMsg>> {
+        if self.done {
+            return None;
+        }
+        let first = mem::replace(&mut self.first, false);
+        if let Some(multi) = &mut self.multi {
+            match multi.next().await {
+                // 5.19 rejects the multishot flag
+                Some(cqe) if first && cqe.res() == -libc::EINVAL => self.multi = None,
+                Some(cqe) => {
+                    // Earlier kernels ignore it and receive once, the
+                    // payload alone into the buffer
+                    if first && !cqe.more() && cqe.res() > 0 {
+                        let (_, msg) = multi.data();
+                        let res = lay_out(&self.group, &self.layout, &cqe, &msg.hdr);
+                        self.multi = None;
+                        return Some(res);
+                    }
+                    return Some(
+                        self.group
+                            .take(&cqe)
+                            .and_then(|buf| RecvMsg::new(buf, self.layout)),
+                    );
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+        let mut msg = SelectHdr::new(&self.layout, self.group.size);
+        let op = op::RecvMsgSelect {
+            fd: self.fd,
+            msg: unsafe { &mut *(&mut msg.hdr as *mut _) },
+            bgid: self.group.bgid,
+            flags: 0,
+        };
+        let (cqe, msg) = unsafe { self.driver.submit(op, msg) }.await;
+        let res = lay_out(&self.group, &self.layout, &cqe, &msg.hdr);
+        if res.is_err() {
+            self.done = true;
+        }
+        Some(res)
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+// A msghdr for a recvmsg into a picked buffer of up to `len` bytes, with its
+// own room for the name and control messages of `layout`. Boxed, it stays
+// put while the kernel writes to it.
+#[derive(Debug)]
+struct SelectHdr {
+    hdr: libc::msghdr,
+    iov: libc::iovec,
+    name: libc::sockaddr_storage,
+    control: Vec<u64>,
+}
+
+impl SelectHdr {
+    fn new(layout: &libc::msghdr, len: usize) -> Box<Self> {
+        #[allow(clippy::unnecessary_cast)] // u32 on musl
+        let control_len = layout.msg_controllen as usize;
+        let mut msg = Box::new(Self {
+            hdr: unsafe { mem::zeroed() },
+            iov: libc::iovec {
+                iov_base: ptr::null_mut(),
+                iov_len: len,
+            },
+            name: unsafe { mem::zeroed() },
+            control: vec![0; control_len.div_ceil(mem::size_of::<u64>())],
+        });
+        msg.hdr.msg_iov = &mut msg.iov;
+        msg.hdr.msg_iovlen = 1;
+        if layout.msg_namelen > 0 {
+            msg.hdr.msg_name = &mut msg.name as *mut _ as *mut _;
+            msg.hdr.msg_namelen = layout.msg_namelen;
+        }
+        if control_len > 0 {
+            msg.hdr.msg_control = msg.control.as_mut_ptr() as *mut _;
+            msg.hdr.msg_controllen = layout.msg_controllen;
+        }
+        msg
+    }
+}
+
+// Takes the buffer picked by a single-shot recvmsg, which holds the payload
+// alone, and lays it out after `layout` as a multishot one would have, from
+// the name and control messages `received`.
+fn lay_out(
+    group: &Rc<BufferGroup>,
+    layout: &libc::msghdr,
+    cqe: &crate::cq::Entry,
+    received: &libc::msghdr,
+) -> Result<RecvMsg> {
+    let mut buf = group.take(cqe)?;
+    if let Some(bid) = buf.bid {
+        let mem = unsafe { slice::from_raw_parts_mut(group.buffer(bid), group.size) };
+        buf.len = msg::lay_out(mem, buf.len, layout, received);
+    }
+    RecvMsg::new(buf, *layout)
+}
+
+// A message received by a RecvMsgStream, which gives its buffer back to the
+// group on drop.
+#[derive(Debug)]
+pub struct RecvMsg {
+    buf: GroupBuf,
+    // The name and control lengths the op was submitted with
+    layout: libc::msghdr,
+}
+
+impl RecvMsg {
+    fn new(buf: GroupBuf, layout: libc::msghdr) -> Result<Self> {
+        if RecvMsgOut::parse(&buf, &layout).is_none() {
+            return Err(Error::new(ErrorKind::InvalidData, "short recvmsg buffer"));
+        }
+        Ok(Self { buf, layout })
+    }
+
+    #[inline]
+    pub fn out(&self) -> RecvMsgOut<'_> {
+        RecvMsgOut::parse(&self.buf, &self.layout).unwrap()
+    }
+
+    #[inline]
+    pub fn buffer_id(&self) -> Option<u16> {
+        self.buf.buffer_id()
+    }
+}
+
+// Connections accepted by a multishot accept, see TcpListener::incoming.
+// Falls back to single-shot accepts on kernels without multishot accept.
+pub struct Incoming<S> {
+    driver: Driver,
+    fd: RawFd,
+    // None once fallen back to single-shot accepts
+    multi: Option<Multishot<()>>,
+    // No cqe received yet
+    first: bool,
+    wrap: fn(Socket) -> S,
+}
+
+impl<S> Incoming<S> {
+    // Returns the next connection, None once the accept stops.
+    pub async fn next(&mut self) -> Option<Result<S>> {
+        let first = mem::replace(&mut self.first, false);
+        if let Some(multi) = &mut self.multi {
+            let cqe = multi.next().await?;
+            // Kernels before 5.19 reject multishot accept
+            if !(first && cqe.res() == -libc::EINVAL) {
+                let fd = cvt(&cqe);
+                return Some(fd.map(|fd| (self.wrap)(Socket::from_raw(&self.driver, fd as RawFd))));
+            }
+            self.multi = None;
+        }
+        let res = accept(&self.driver, self.fd).await;
+        Some(res.map(|(socket, _)| (self.wrap)(socket)))
+    }
+}
+
+impl<S> fmt::Debug for Incoming<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Incoming")
+            .field("fd", &self.fd)
+            .field("multi", &self.multi)
+            .finish()
+    }
+}
+
+async fn accept(driver: &Driver, fd: RawFd) -> Result<(Socket, SockAddr)> {
+    let mut addr = Box::new(SockAddr::empty());
+    let op = op::Accept {
+        fd,
+        addr: unsafe { &mut *addr.as_mut_ptr() },
+        addr_len: unsafe { &mut *(addr.len_mut() as *mut _) },
+        flags: libc::SOCK_CLOEXEC as u32,
+    };
+    let completion = unsafe { driver.submit(op, addr) }.discard_with(close_fd);
+    let (cqe, addr) = completion.await;
+    let fd = cvt(&cqe)? as RawFd;
+    Ok((Socket::from_raw(driver, fd), *addr))
+}
+
+// Closes the fd of an accept or socket cqe left behind by a dropped future
+// or Incoming.
+fn close_fd(cqe: &crate::cq::Entry) {
+    if cqe.res() >= 0 {
+        unsafe { libc::close(cqe.res()) };
+    }
+}
+
+async fn recv_select(driver: &Driver, fd: RawFd, group: &Rc<BufferGroup>) -> Result<GroupBuf> {
+    let op = op::RecvSelect {
+        sockfd: fd,
+        len: group.size as u32,
+        bgid: group.bgid,
+        flags: 0,
+        multishot: false,
+    };
+    let (cqe, group) = unsafe { driver.submit(op, group.clone()) }.await;
+    group.take(&cqe)
+}
+
+// The socket shared by the types below, closed with op::Close on drop
+#[derive(Debug)]
+pub(crate) struct Socket {
+    driver: Driver,
+    fd: RawFd,
+}
+
+impl Socket {
+    // Creates a socket with op::Socket if the kernel supports it.
+    async fn new(driver: &Driver, domain: i32, ty: i32) -> Result<Self> {
+        let ty = ty | libc::SOCK_CLOEXEC;
+        let fd = if driver.supports::<op::Socket>() {
+            let op = op::Socket {
+                domain,
+                ty,
+                protocol: 0,
+            };
+            let completion = unsafe { driver.submit(op, ()) }.discard_with(close_fd);
+            let (cqe, ()) = completion.await;
+            cvt(&cqe)? as RawFd
+        } else {
+            sys::cvt(unsafe { libc::socket(domain, ty, 0) })?
+        };
+        Ok(Self::from_raw(driver, fd))
+    }
+
+    #[inline]
+    fn from_raw(driver: &Driver, fd: RawFd) -> Self {
+        Self {
+            driver: driver.clone(),
+            fd,
+        }
+    }
+
+    #[inline]
+    fn bind(&self, addr: &SockAddr) -> Result<()> {
+        sys::cvt(unsafe { libc::bind(self.fd, addr.as_ptr(), addr.len()) }).map(drop)
+    }
+
+    // Lets a listener bind while connections of a previous one linger in
+    // TIME_WAIT, as std does for TcpListener.
+    fn reuse_addr(&self) -> Result<()> {
+        let on: libc::c_int = 1;
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEADDR,
+                &on as *const _ as *const _,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        sys::cvt(ret).map(drop)
+    }
+
+    #[inline]
+    fn listen(&self) -> Result<()> {
+        sys::cvt(unsafe { libc::listen(self.fd, BACKLOG) }).map(drop)
+    }
+
+    async fn connect(&self, addr: SockAddr) -> Result<()> {
+        let addr = Box::new(addr);
+        let op = op::Connect {
+            fd: self.fd,
+            addr: unsafe { &*addr.as_ptr() },
+            addr_len: addr.len(),
+        };
+        let (cqe, _) = unsafe { self.driver.submit(op, addr) }.await;
+        cvt(&cqe).map(drop)
+    }
+
+    #[inline]
+    async fn accept(&self) -> Result<(Socket, SockAddr)> {
+        accept(&self.driver, self.fd).await
+    }
+
+    #[inline]
+    fn incoming<S>(&self, wrap: fn(Socket) -> S) -> Incoming<S> {
+        let op = op::AcceptMulti {
+            fd: self.fd,
+            flags: libc::SOCK_CLOEXEC as u32,
+        };
+        Incoming {
+            driver: self.driver.clone(),
+            fd: self.fd,
+            multi: Some(unsafe { self.driver.submit_multi(op, ()) }.discard_with(close_fd)),
+            first: true,
+            wrap,
+        }
+    }
+
+    async fn recv(&self, mut buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        let op = op::Recv {
+            sockfd: self.fd,
+            buf: unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) },
+            flags: 0,
+        };
+        let (cqe, buf) = unsafe { self.driver.submit(op, buf) }.await;
+        (cvt(&cqe).map(|n| n as usize), buf)
+    }
+
+    async fn send(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        let op = op::Send {
+            sockfd: self.fd,
+            data: unsafe { slice::from_raw_parts(buf.as_ptr(), buf.len()) },
+            flags: libc::MSG_NOSIGNAL as u32,
+        };
+        let (cqe, buf) = unsafe { self.driver.submit(op, buf) }.await;
+        (cvt(&cqe).map(|n| n as usize), buf)
+    }
+
+    #[inline]
+    async fn recv_select(&self, group: &Rc<BufferGroup>) -> Result<GroupBuf> {
+        recv_select(&self.driver, self.fd, group).await
+    }
+
+    #[inline]
+    fn recv_multi(&self, group: &Rc<BufferGroup>) -> RecvStream {
+        let op = op::RecvSelect {
+            sockfd: self.fd,
+            len: 0,
+            bgid: group.bgid,
+            flags: 0,
+            multishot: true,
+        };
+        RecvStream {
+            driver: self.driver.clone(),
+            fd: self.fd,
+            group: group.clone(),
+            multi: Some(unsafe { self.driver.submit_multi(op, group.clone()) }),
+            first: true,
+            done: false,
+        }
+    }
+
+    async fn send_msg(&self, mut msg: MsgHdr, flags: u32) -> (Result<usize>, MsgHdr) {
+        let op = op::SendMsg {
+            fd: self.fd,
+            msg: unsafe { &*(msg.as_raw_mut() as *const _) },
+            flags: flags | libc::MSG_NOSIGNAL as u32,
+        };
+        let (cqe, msg) = unsafe { self.driver.submit(op, msg) }.await;
+        (cvt(&cqe).map(|n| n as usize), msg)
+    }
+
+    async fn recv_msg(&self, mut msg: MsgHdr, flags: u32) -> (Result<usize>, MsgHdr) {
+        let op = op::RecvMsg {
+            fd: self.fd,
+            msg: unsafe { &mut *(msg.as_raw_mut() as *mut _) },
+            flags,
+        };
+        let (cqe, msg) = unsafe { self.driver.submit(op, msg) }.await;
+        (cvt(&cqe).map(|n| n as usize), msg)
+    }
+
+    fn recv_msg_multi(&self, group: &Rc<BufferGroup>, mut msg: MsgHdr) -> RecvMsgStream {
+        let raw = msg.as_raw_mut();
+        let mut layout: libc::msghdr = unsafe { mem::zeroed() };
+        layout.msg_namelen = cmp::min(
+            raw.msg_namelen,
+            mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+        );
+        layout.msg_controllen = raw.msg_controllen;
+        // The whole buffer, should the kernel ignore the multishot flag
+        let mut hdr = SelectHdr::new(&layout, group.size);
+        let op = op::RecvMsgMulti {
+            fd: self.fd,
+            msg: unsafe { &*(&mut hdr.hdr as *mut _ as *const _) },
+            bgid: group.bgid,
+            flags: 0,
+        };
+        RecvMsgStream {
+            driver: self.driver.clone(),
+            fd: self.fd,
+            group: group.clone(),
+            layout,
+            multi: Some(unsafe { self.driver.submit_multi(op, (group.clone(), hdr)) }),
+            first: true,
+            done: false,
+        }
+    }
+
+    fn local_addr(&self) -> Result<SockAddr> {
+        let mut addr = SockAddr::empty();
+        let ret = unsafe { libc::getsockname(self.fd, addr.as_mut_ptr(), addr.len_mut()) };
+        sys::cvt(ret)?;
+        Ok(addr)
+    }
+
+    fn peer_addr(&self) -> Result<SockAddr> {
+        let mut addr = SockAddr::empty();
+        let ret = unsafe { libc::getpeername(self.fd, addr.as_mut_ptr(), addr.len_mut()) };
+        sys::cvt(ret)?;
+        Ok(addr)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        let how = match how {
+            Shutdown::Read => libc::SHUT_RD,
+            Shutdown::Write => libc::SHUT_WR,
+            Shutdown::Both => libc::SHUT_RDWR,
+        };
+        sys::cvt(unsafe { libc::shutdown(self.fd, how) }).map(drop)
+    }
+
+    #[inline]
+    fn into_raw_fd(mut self) -> RawFd {
+        mem::replace(&mut self.fd, -1)
+    }
+}
+
+impl Drop for Socket {
+    #[inline]
+    fn drop(&mut self) {
+        if self.fd >= 0 {
+            self.driver.close(self.fd);
+        }
+    }
+}
+
+#[inline]
+fn domain(addr: &SocketAddr) -> i32 {
+    match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpListener {
+    socket: Socket,
+}
+
+impl TcpListener {
+    pub async fn bind(driver: &Driver, addr: SocketAddr) -> Result<Self> {
+        let socket = Socket::new(driver, domain(&addr), libc::SOCK_STREAM).await?;
+        socket.reuse_addr()?;
+        socket.bind(&SockAddr::from_inet(&addr))?;
+        socket.listen()?;
+        Ok(Self { socket })
+    }
+
+    #[inline]
+    pub fn from_std(driver: &Driver, listener: std::net::TcpListener) -> Self {
+        Self {
+            socket: Socket::from_raw(driver, listener.into_raw_fd()),
+        }
+    }
+
+    #[inline]
+    pub async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        let (socket, addr) = self.socket.accept().await?;
+        Ok((TcpStream { socket }, addr.inet()?))
+    }
+
+    // Accepts connections with a single multishot accept, which the kernel
+    // supports since 5.19, and with an accept per connection before.
+    #[inline]
+    pub fn incoming(&self) -> Incoming<TcpStream> {
+        self.socket.incoming(|socket| TcpStream { socket })
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()?.inet()
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpStream {
+    socket: Socket,
+}
+
+impl TcpStream {
+    pub async fn connect(driver: &Driver, addr: SocketAddr) -> Result<Self> {
+        let socket = Socket::new(driver, domain(&addr), libc::SOCK_STREAM).await?;
+        socket.connect(SockAddr::from_inet(&addr)).await?;
+        Ok(Self { socket })
+    }
+
+    #[inline]
+    pub fn from_std(driver: &Driver, stream: std::net::TcpStream) -> Self {
+        Self {
+            socket: Socket::from_raw(driver, stream.into_raw_fd()),
+        }
+    }
+
+    #[inline]
+    pub async fn read(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        self.socket.recv(buf).await
+    }
+
+    #[inline]
+    pub async fn write(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        self.socket.send(buf).await
+    }
+
+    // Receives into a buffer picked from `group`.
+    #[inline]
+    pub async fn recv_select(&self, group: &Rc<BufferGroup>) -> Result<GroupBuf> {
+        self.socket.recv_select(group).await
+    }
+
+    // Receives into buffers picked from `group` with a single multishot
+    // receive, which the kernel supports since 6.0, and with a receive per
+    // buffer before.
+    #[inline]
+    pub fn recv_multi(&self, group: &Rc<BufferGroup>) -> RecvStream {
+        self.socket.recv_multi(group)
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()?.inet()
+    }
+
+    #[inline]
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.socket.peer_addr()?.inet()
+    }
+
+    #[inline]
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.socket.shutdown(how)
+    }
+}
+
+#[derive(Debug)]
+pub struct UdpSocket {
+    socket: Socket,
+}
+
+impl UdpSocket {
+    pub async fn bind(driver: &Driver, addr: SocketAddr) -> Result<Self> {
+        let socket = Socket::new(driver, domain(&addr), libc::SOCK_DGRAM).await?;
+        socket.bind(&SockAddr::from_inet(&addr))?;
+        Ok(Self { socket })
+    }
+
+    #[inline]
+    pub fn from_std(driver: &Driver, socket: std::net::UdpSocket) -> Self {
+        Self {
+            socket: Socket::from_raw(driver, socket.into_raw_fd()),
+        }
+    }
+
+    // Sets the default destination of `send` and the only source of `recv`.
+    #[inline]
+    pub async fn connect(&self, addr: SocketAddr) -> Result<()> {
+        self.socket.connect(SockAddr::from_inet(&addr)).await
+    }
+
+    #[inline]
+    pub async fn send(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        self.socket.send(buf).await
+    }
+
+    #[inline]
+    pub async fn recv(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        self.socket.recv(buf).await
+    }
+
+    pub async fn send_to(&self, buf: Vec<u8>, addr: SocketAddr) -> (Result<usize>, Vec<u8>) {
+        let msg = MsgHdr::new().buf(buf).name(addr);
+        let (res, msg) = self.socket.send_msg(msg, 0).await;
+        (res, msg.into_bufs().pop().unwrap())
+    }
+
+    pub async fn recv_from(&self, buf: Vec<u8>) -> (Result<(usize, SocketAddr)>, Vec<u8>) {
+        let msg = MsgHdr::new().buf(buf).name_space();
+        let (res, msg) = self.socket.recv_msg(msg, 0).await;
+        let res = res.and_then(|n| Ok((n, msg.source().unwrap()?)));
+        (res, msg.into_bufs().pop().unwrap())
+    }
+
+    // Sends `msg`, which may carry UDP_SEGMENT or SO_TIMESTAMPING cmsgs.
+    #[inline]
+    pub async fn send_msg(&self, msg: MsgHdr, flags: u32) -> (Result<usize>, MsgHdr) {
+        self.socket.send_msg(msg, flags).await
+    }
+
+    // Receives into `msg`, whose cmsgs are decoded with MsgHdr::cmsgs.
+    #[inline]
+    pub async fn recv_msg(&self, msg: MsgHdr, flags: u32) -> (Result<usize>, MsgHdr) {
+        self.socket.recv_msg(msg, flags).await
+    }
+
+    // Receives messages into buffers picked from `group` with a single
+    // multishot recvmsg, which the kernel supports since 6.0, or one recvmsg
+    // per message before. Only the room made in `msg` for the name and
+    // control messages is used, its buffers are ignored.
+    #[inline]
+    pub fn recv_msg_multi(&self, group: &Rc<BufferGroup>, msg: MsgHdr) -> RecvMsgStream {
+        self.socket.recv_msg_multi(group, msg)
+    }
+
+    #[inline]
+    pub async fn recv_select(&self, group: &Rc<BufferGroup>) -> Result<GroupBuf> {
+        self.socket.recv_select(group).await
+    }
+
+    #[inline]
+    pub fn recv_multi(&self, group: &Rc<BufferGroup>) -> RecvStream {
+        self.socket.recv_multi(group)
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()?.inet()
+    }
+}
+
+#[derive(Debug)]
+pub struct UnixListener {
+    socket: Socket,
+}
+
+impl UnixListener {
+    pub async fn bind<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<Self> {
+        let addr = SockAddr::from_path(path.as_ref())?;
+        let socket = Socket::new(driver, libc::AF_UNIX, libc::SOCK_STREAM).await?;
+        socket.bind(&addr)?;
+        socket.listen()?;
+        Ok(Self { socket })
+    }
+
+    #[inline]
+    pub fn from_std(driver: &Driver, listener: std::os::unix::net::UnixListener) -> Self {
+        Self {
+            socket: Socket::from_raw(driver, listener.into_raw_fd()),
+        }
+    }
+
+    #[inline]
+    pub async fn accept(&self) -> Result<UnixStream> {
+        let (socket, _) = self.socket.accept().await?;
+        Ok(UnixStream { socket })
+    }
+
+    #[inline]
+    pub fn incoming(&self) -> Incoming<UnixStream> {
+        self.socket.incoming(|socket| UnixStream { socket })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnixStream {
+    socket: Socket,
+}
+
+impl UnixStream {
+    pub async fn connect<P: AsRef<Path>>(driver: &Driver, path: P) -> Result<Self> {
+        let addr = SockAddr::from_path(path.as_ref())?;
+        let socket = Socket::new(driver, libc::AF_UNIX, libc::SOCK_STREAM).await?;
+        socket.connect(addr).await?;
+        Ok(Self { socket })
+    }
+
+    pub fn pair(driver: &Driver) -> Result<(Self, Self)> {
+        let mut fds = [0; 2];
+        let ty = libc::SOCK_STREAM | libc::SOCK_CLOEXEC;
+        let ret = unsafe { libc::socketpair(libc::AF_UNIX, ty, 0, fds.as_mut_ptr()) };
+        sys::cvt(ret)?;
+        Ok((
+            Self {
+                socket: Socket::from_raw(driver, fds[0]),
+            },
+            Self {
+                socket: Socket::from_raw(driver, fds[1]),
+            },
+        ))
+    }
+
+    #[inline]
+    pub fn from_std(driver: &Driver, stream: std::os::unix::net::UnixStream) -> Self {
+        Self {
+            socket: Socket::from_raw(driver, stream.into_raw_fd()),
+        }
+    }
+
+    #[inline]
+    pub async fn read(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        self.socket.recv(buf).await
+    }
+
+    #[inline]
+    pub async fn write(&self, buf: Vec<u8>) -> (Result<usize>, Vec<u8>) {
+        self.socket.send(buf).await
+    }
+
+    #[inline]
+    pub async fn recv_select(&self, group: &Rc<BufferGroup>) -> Result<GroupBuf> {
+        self.socket.recv_select(group).await
+    }
+
+    #[inline]
+    pub fn recv_multi(&self, group: &Rc<BufferGroup>) -> RecvStream {
+        self.socket.recv_multi(group)
+    }
+
+    // Sends `msg`, which may pass fds with Cmsg::Rights.
+    #[inline]
+    pub async fn send_msg(&self, msg: MsgHdr, flags: u32) -> (Result<usize>, MsgHdr) {
+        self.socket.send_msg(msg, flags).await
+    }
+
+    #[inline]
+    pub async fn recv_msg(&self, msg: MsgHdr, flags: u32) -> (Result<usize>, MsgHdr) {
+        self.socket.recv_msg(msg, flags).await
+    }
+
+    #[inline]
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.socket.shutdown(how)
+    }
+}
+
+macro_rules! impl_fd {
+    ($($ty:ty),*) => {
+        $(
+            impl AsRawFd for $ty {
+                #[inline]
+                fn as_raw_fd(&self) -> RawFd {
+                    self.socket.fd
+                }
+            }
+
+            impl IntoRawFd for $ty {
+                #[inline]
+                fn into_raw_fd(self) -> RawFd {
+                    self.socket.into_raw_fd()
+                }
+            }
+        )*
+    };
+}
+
+impl_fd!(TcpListener, TcpStream, UdpSocket, UnixListener, UnixStream);
@@ -0,0 +1,110 @@
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::sys::Backend;
+use crate::uring::Fd;
+use crate::{cq, sys, Uring};
+
+// An eventfd registered with a ring, signaled when completions are posted.
+//
+// Its fd can be handed to epoll, mio or any other readiness based event loop;
+// once it is readable, `drain` resets it and reaps the ring. Dropping the
+// notifier unregisters the eventfd, so that the ring can take another one.
+#[derive(Debug)]
+pub struct EventfdNotifier {
+    fd: Fd,
+    // The ring it is registered with
+    ring: Arc<Fd>,
+    backend: Rc<dyn Backend>,
+}
+
+impl EventfdNotifier {
+    #[inline]
+    pub fn register(uring: &Uring<'_>) -> Result<Self> {
+        let fd = Self::eventfd()?;
+        unsafe { uring.register_eventfd(fd.as_raw_fd())? };
+        Ok(Self::new(fd, uring))
+    }
+
+    // Only signaled for requests completed asynchronously, not for those
+    // completed inline during submission.
+    #[inline]
+    pub fn register_async(uring: &Uring<'_>) -> Result<Self> {
+        let fd = Self::eventfd()?;
+        unsafe { uring.register_eventfd_async(fd.as_raw_fd())? };
+        Ok(Self::new(fd, uring))
+    }
+
+    #[inline]
+    fn eventfd() -> Result<Fd> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        sys::cvt(fd)?;
+        Ok(Fd::new(fd))
+    }
+
+    #[inline]
+    fn new(fd: Fd, uring: &Uring<'_>) -> Self {
+        Self {
+            fd,
+            ring: uring.shared_fd().clone(),
+            backend: uring.backend().clone(),
+        }
+    }
+
+    // Resets the eventfd. Returns the number of signals since the last reset.
+    pub fn clear(&self) -> Result<u64> {
+        let mut count = 0u64;
+        let ret = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut count as *mut u64 as *mut _,
+                mem::size_of::<u64>(),
+            )
+        };
+        if ret >= 0 {
+            return Ok(count);
+        }
+        let err = Error::last_os_error();
+        if err.kind() == ErrorKind::WouldBlock {
+            Ok(0)
+        } else {
+            Err(err)
+        }
+    }
+
+    // Resets the eventfd and hands every completion in the CQ ring to `f`.
+    #[inline]
+    pub fn drain<F>(&self, uring: &mut Uring<'_>, f: F) -> Result<u32>
+    where
+        F: FnMut(cq::Entry),
+    {
+        self.clear()?;
+        uring.reap(f)
+    }
+}
+
+impl Drop for EventfdNotifier {
+    fn drop(&mut self) {
+        // Before the eventfd is closed, so that its number is not signaled
+        // once reused
+        let _ = unsafe {
+            self.backend.register(
+                self.ring.as_raw_fd(),
+                Uring::UNREGISTER_EVENTFD,
+                ptr::null(),
+                0,
+            )
+        };
+    }
+}
+
+impl AsRawFd for EventfdNotifier {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
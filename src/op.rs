@@ -1,13 +1,17 @@
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::io::{IoSlice, IoSliceMut};
 use std::mem;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr;
+use std::time::{Duration, Instant};
+
+use bitflags::bitflags;
 
 use crate::sq;
+use crate::uring::RingHandle;
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Code {
     Nop,
     Readv,
@@ -42,12 +46,56 @@ pub enum Code {
     Splice,
     ProvideBuffers,
     RemoveBuffers,
+    Tee,
+    Shutdown,
+    RenameAt,
+    UnlinkAt,
+    MkdirAt,
+    SymlinkAt,
+    LinkAt,
+    MsgRing,
+    FSetXattr,
+    SetXattr,
+    FGetXattr,
+    GetXattr,
+    Socket,
+}
+
+impl Code {
+    // The last op known to the crate
+    pub(crate) const LAST: Code = Code::Socket;
+
+    // The op of an sqe opcode, None if unknown to the crate.
+    #[inline]
+    pub fn from_u8(opcode: u8) -> Option<Self> {
+        if opcode <= Self::LAST as u8 {
+            // The variants are numbered from 0 with no gaps
+            Some(unsafe { mem::transmute::<u8, Code>(opcode) })
+        } else {
+            None
+        }
+    }
 }
 
+// IOSQE_BUFFER_SELECT, pick a buffer from the group in buf_group
+const BUFFER_SELECT: u8 = 1 << 5;
+
+// An io_uring operation, prepared into a sqe by `prepare`.
+//
+// `prepare` takes `&mut self` so that memory the kernel writes to, such as a
+// read buffer, is reached through unique borrows and handed over as a
+// mutable pointer; memory the kernel only reads may be shared.
 pub trait Op {
     const CODE: u8;
 
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry>;
+    /// # Safety
+    ///
+    /// Callers must keep the memory the op refers to alive and unaccessed
+    /// until the op completes, except for shared reads of memory the kernel
+    /// only reads. Memory inline in the op, such as the timespec of Timeout,
+    /// is read when the sqe is submitted, so the op must not be moved or
+    /// dropped before then.
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry>;
 }
 
 #[derive(Debug)]
@@ -57,7 +105,7 @@ impl Op for Nop {
     const CODE: u8 = Code::Nop as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(Self::CODE, -1, ptr::null(), 0, 0)
     }
 }
@@ -65,7 +113,7 @@ impl Op for Nop {
 #[derive(Debug)]
 pub struct Readv<'a> {
     pub fd: RawFd,
-    pub iovecs: &'a [IoSliceMut<'a>],
+    pub iovecs: &'a mut [IoSliceMut<'a>],
     pub offset: u64,
 }
 
@@ -73,11 +121,11 @@ impl Op for Readv<'_> {
     const CODE: u8 = Code::Readv as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.fd,
-            self.iovecs.as_ptr() as *const _,
+            self.iovecs.as_mut_ptr() as *const _,
             self.iovecs.len() as u32,
             self.offset,
         )
@@ -95,7 +143,7 @@ impl Op for Writev<'_> {
     const CODE: u8 = Code::Writev as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.fd,
@@ -106,20 +154,27 @@ impl Op for Writev<'_> {
     }
 }
 
+// IORING_FSYNC_ flags
+bitflags! {
+    pub struct FsyncFlags: u32 {
+        const DATASYNC = 1 << 0; // fdatasync(2) instead of fsync(2)
+    }
+}
+
 #[derive(Debug)]
 pub struct Fsync {
     pub fd: RawFd,
-    pub flags: u32,
+    pub flags: FsyncFlags,
 }
 
 impl Op for Fsync {
     const CODE: u8 = Code::Fsync as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.fd, ptr::null(), 0, 0) {
             Some(sqe) => {
-                sqe.set_fsync_flags(self.flags);
+                sqe.set_fsync_flags(self.flags.bits());
                 Some(sqe)
             }
             None => None,
@@ -139,11 +194,11 @@ impl Op for ReadFixed<'_> {
     const CODE: u8 = Code::ReadFixed as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.fd,
-            self.buf.as_ptr() as *const _,
+            self.buf.as_mut_ptr() as *const _,
             self.buf.len() as u32,
             self.offset,
         ) {
@@ -168,7 +223,7 @@ impl Op for WriteFixed<'_> {
     const CODE: u8 = Code::WriteFixed as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.fd,
@@ -195,7 +250,7 @@ impl Op for PollAdd {
     const CODE: u8 = Code::PollAdd as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.fd, ptr::null(), 0, 0) {
             Some(sqe) => {
                 sqe.set_poll_events(self.poll_mask);
@@ -216,7 +271,7 @@ impl Op for PollRemove {
     const CODE: u8 = Code::PollRemove as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(Self::CODE, self.fd, self.user_data as _, 0, 0)
     }
 }
@@ -233,7 +288,7 @@ impl Op for SyncFileRange {
     const CODE: u8 = Code::SyncFileRange as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.fd, ptr::null(), self.len, self.offset) {
             Some(sqe) => {
                 sqe.set_sync_range_flags(self.flags);
@@ -255,7 +310,7 @@ impl Op for SendMsg<'_> {
     const CODE: u8 = Code::SendMsg as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.fd, self.msg as *const _ as *const _, 1, 0) {
             Some(sqe) => {
                 sqe.set_msg_flags(self.flags);
@@ -277,10 +332,51 @@ impl Op for RecvMsg<'_> {
     const CODE: u8 = Code::RecvMsg as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.fd,
+            &mut *self.msg as *mut _ as *const _,
+            1,
+            0,
+        ) {
+            Some(sqe) => {
+                sqe.set_msg_flags(self.flags);
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Receives messages into buffers picked from the group `bgid` until an
+// error occurs. Only msg_namelen and msg_controllen of `msg` are used: each
+// buffer starts with an io_uring_recvmsg_out header followed by that much
+// room for the name and control messages, then the payload. See
+// RecvMsgOut to parse it.
+#[derive(Debug)]
+pub struct RecvMsgMulti<'a> {
+    pub fd: RawFd,
+    pub msg: &'a libc::msghdr,
+    pub bgid: u16,
+    pub flags: u32,
+}
+
+impl RecvMsgMulti<'_> {
+    const MULTISHOT: u16 = 1 << 1;
+}
+
+impl Op for RecvMsgMulti<'_> {
+    const CODE: u8 = Code::RecvMsg as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.fd, self.msg as *const _ as *const _, 1, 0) {
             Some(sqe) => {
                 sqe.set_msg_flags(self.flags);
+                sqe.set_flags(BUFFER_SELECT);
+                sqe.set_buf_group(self.bgid);
+                sqe.set_ioprio(Self::MULTISHOT);
                 Some(sqe)
             }
             None => None,
@@ -288,27 +384,126 @@ impl Op for RecvMsg<'_> {
     }
 }
 
+// Receives a message into a buffer picked from the group `bgid`. `msg` has a
+// single iovec, whose length caps the payload; only the payload goes to the
+// buffer, the name and control messages go to those of `msg`.
 #[derive(Debug)]
-pub struct Timeout<'a> {
-    pub ts: &'a libc::timespec,
-    pub count: u32,
+pub struct RecvMsgSelect<'a> {
+    pub fd: RawFd,
+    pub msg: &'a mut libc::msghdr,
+    pub bgid: u16,
     pub flags: u32,
 }
 
-impl Op for Timeout<'_> {
+impl Op for RecvMsgSelect<'_> {
+    const CODE: u8 = Code::RecvMsg as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.fd,
+            &mut *self.msg as *mut _ as *const _,
+            1,
+            0,
+        ) {
+            Some(sqe) => {
+                sqe.set_msg_flags(self.flags);
+                sqe.set_flags(BUFFER_SELECT);
+                sqe.set_buf_group(self.bgid);
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn timespec(dur: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: dur.as_secs() as libc::time_t,
+        tv_nsec: dur.subsec_nanos() as libc::c_long,
+    }
+}
+
+// IORING_TIMEOUT_ flags
+bitflags! {
+    pub struct TimeoutFlags: u32 {
+        const ABS           = 1 << 0; // ts is an absolute time
+        const BOOTTIME      = 1 << 2; // use CLOCK_BOOTTIME
+        const REALTIME      = 1 << 3; // use CLOCK_REALTIME
+        const ETIME_SUCCESS = 1 << 5; // -ETIME does not break a link chain
+        const MULTISHOT     = 1 << 6; // rearm after each expiry, count times
+    }
+}
+
+impl TimeoutFlags {
+    const UPDATE: u32 = 1 << 1;
+    const LINK_TIMEOUT_UPDATE: u32 = 1 << 4;
+}
+
+// The kernel copies `ts` when the sqe is submitted, so the op has to be kept
+// alive until then.
+#[derive(Debug)]
+pub struct Timeout {
+    pub ts: libc::timespec,
+    pub count: u32,
+    pub flags: TimeoutFlags,
+}
+
+impl Timeout {
+    #[inline]
+    pub fn after(dur: Duration) -> Self {
+        Self {
+            ts: timespec(dur),
+            count: 0,
+            flags: TimeoutFlags::empty(),
+        }
+    }
+
+    // Expires at `deadline` on CLOCK_MONOTONIC, the clock backing Instant.
+    pub fn at(deadline: Instant) -> Self {
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let dur = deadline.saturating_duration_since(Instant::now())
+            + Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+        Self {
+            ts: timespec(dur),
+            count: 0,
+            flags: TimeoutFlags::ABS,
+        }
+    }
+
+    #[inline]
+    pub fn count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+
+    #[inline]
+    pub fn flags(mut self, flags: TimeoutFlags) -> Self {
+        self.flags |= flags;
+        self
+    }
+}
+
+impl Op for Timeout {
     const CODE: u8 = Code::Timeout as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             -1,
-            self.ts as *const _ as *const _,
+            &self.ts as *const _ as *const _,
             1,
             self.count as u64,
         ) {
             Some(sqe) => {
-                sqe.set_timeout_flags(self.flags);
+                sqe.set_timeout_flags(self.flags.bits());
                 Some(sqe)
             }
             None => None,
@@ -316,20 +511,55 @@ impl Op for Timeout<'_> {
     }
 }
 
+// Removes the Timeout submitted with `user_data`. The kernel takes no flags
+// for a removal; updates go through TimeoutUpdate.
 #[derive(Debug)]
 pub struct TimeoutRemove {
     pub user_data: u64,
-    pub flags: u32,
 }
 
 impl Op for TimeoutRemove {
     const CODE: u8 = Code::TimeoutRemove as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
-        match sq.prep_rw(Self::CODE, -1, self.user_data as *const _, 0, 0) {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        sq.prep_rw(Self::CODE, -1, self.user_data as *const _, 0, 0)
+    }
+}
+
+// Updates the expiry of the Timeout, or the LinkTimeout if `link` is set,
+// submitted with `user_data`. `ts` is an absolute time if `absolute` is set,
+// the only flag the kernel takes for an update.
+#[derive(Debug)]
+pub struct TimeoutUpdate {
+    pub user_data: u64,
+    pub ts: libc::timespec,
+    pub absolute: bool,
+    pub link: bool,
+}
+
+impl Op for TimeoutUpdate {
+    const CODE: u8 = Code::TimeoutRemove as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            -1,
+            self.user_data as *const _,
+            0,
+            &self.ts as *const _ as u64,
+        ) {
             Some(sqe) => {
-                sqe.set_timeout_flags(self.flags);
+                let mut flags = if self.link {
+                    TimeoutFlags::LINK_TIMEOUT_UPDATE
+                } else {
+                    TimeoutFlags::UPDATE
+                };
+                if self.absolute {
+                    flags |= TimeoutFlags::ABS.bits();
+                }
+                sqe.set_timeout_flags(flags);
                 Some(sqe)
             }
             None => None,
@@ -349,13 +579,13 @@ impl Op for Accept<'_> {
     const CODE: u8 = Code::Accept as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.fd,
-            self.addr as *const _ as *const _,
+            &mut *self.addr as *mut _ as *const _,
             0,
-            self.addr_len as *const _ as u64,
+            &mut *self.addr_len as *mut _ as u64,
         ) {
             Some(sqe) => {
                 sqe.set_accept_flags(self.flags);
@@ -366,20 +596,114 @@ impl Op for Accept<'_> {
     }
 }
 
+// Accepts connections until canceled or an error occurs, posting a
+// completion with the fd of each.
+#[derive(Debug)]
+pub struct AcceptMulti {
+    pub fd: RawFd,
+    pub flags: u32,
+}
+
+impl AcceptMulti {
+    const MULTISHOT: u16 = 1 << 0;
+}
+
+impl Op for AcceptMulti {
+    const CODE: u8 = Code::Accept as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(Self::CODE, self.fd, ptr::null(), 0, 0) {
+            Some(sqe) => {
+                sqe.set_accept_flags(self.flags);
+                sqe.set_ioprio(Self::MULTISHOT);
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// IORING_ASYNC_CANCEL_ flags
+bitflags! {
+    pub struct CancelFlags: u32 {
+        const ALL       = 1 << 0; // cancel all requests that match
+        const FD        = 1 << 1; // match by fd instead of user_data
+        const ANY       = 1 << 2; // match any request
+        const FD_FIXED  = 1 << 3; // fd is a fixed file index
+    }
+}
+
+// Without CancelFlags::FD or CancelFlags::FD_FIXED, `fd` is ignored and the
+// request is matched by `user_data`.
 #[derive(Debug)]
 pub struct Cancel {
     pub user_data: u64,
-    pub flags: u32,
+    pub fd: RawFd,
+    pub flags: CancelFlags,
+}
+
+impl Cancel {
+    #[inline]
+    pub const fn user_data(user_data: u64) -> Self {
+        Self {
+            user_data,
+            fd: -1,
+            flags: CancelFlags::empty(),
+        }
+    }
+
+    #[inline]
+    pub const fn fd(fd: RawFd) -> Self {
+        Self {
+            user_data: 0,
+            fd,
+            flags: CancelFlags::FD,
+        }
+    }
+
+    #[inline]
+    pub const fn any() -> Self {
+        Self {
+            user_data: 0,
+            fd: -1,
+            flags: CancelFlags::ANY,
+        }
+    }
+
+    #[inline]
+    pub fn all(mut self) -> Self {
+        self.flags |= CancelFlags::ALL;
+        self
+    }
+
+    #[inline]
+    fn target_fd(&self) -> RawFd {
+        if self
+            .flags
+            .intersects(CancelFlags::FD | CancelFlags::FD_FIXED)
+        {
+            self.fd
+        } else {
+            -1
+        }
+    }
 }
 
 impl Op for Cancel {
     const CODE: u8 = Code::AsyncCancel as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
-        match sq.prep_rw(Self::CODE, -1, self.user_data as *const _, 0, 0) {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.target_fd(),
+            self.user_data as *const _,
+            0,
+            0,
+        ) {
             Some(sqe) => {
-                sqe.set_cancel_flags(self.flags);
+                sqe.set_cancel_flags(self.flags.bits());
                 Some(sqe)
             }
             None => None,
@@ -387,20 +711,59 @@ impl Op for Cancel {
     }
 }
 
+// Argument of IORING_REGISTER_SYNC_CANCEL
+// struct io_uring_sync_cancel_reg
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct SyncCancelReg {
+    addr: u64,
+    fd: i32,
+    flags: u32,
+    timeout: libc::timespec,
+    opcode: u8,
+    _pad: [u8; 7],
+    _pad2: [u64; 3],
+}
+
+impl SyncCancelReg {
+    #[inline]
+    pub fn new(cancel: &Cancel, timeout: libc::timespec) -> Self {
+        Self {
+            addr: cancel.user_data,
+            fd: cancel.target_fd(),
+            flags: cancel.flags.bits(),
+            timeout,
+            opcode: 0,
+            _pad: [0; 7],
+            _pad2: [0; 3],
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct LinkTimeout<'a> {
-    pub ts: &'a libc::timespec,
-    pub flags: u32,
+pub struct LinkTimeout {
+    pub ts: libc::timespec,
+    pub flags: TimeoutFlags,
+}
+
+impl LinkTimeout {
+    #[inline]
+    pub fn after(dur: Duration) -> Self {
+        Self {
+            ts: timespec(dur),
+            flags: TimeoutFlags::empty(),
+        }
+    }
 }
 
-impl Op for LinkTimeout<'_> {
+impl Op for LinkTimeout {
     const CODE: u8 = Code::LinkTimeout as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
-        match sq.prep_rw(Self::CODE, -1, self.ts as *const _ as *const _, 1, 0) {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(Self::CODE, -1, &self.ts as *const _ as *const _, 1, 0) {
             Some(sqe) => {
-                sqe.set_timeout_flags(self.flags);
+                sqe.set_timeout_flags(self.flags.bits());
                 Some(sqe)
             }
             None => None,
@@ -419,7 +782,7 @@ impl Op for Connect<'_> {
     const CODE: u8 = Code::Connect as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.fd,
@@ -442,7 +805,7 @@ impl Op for Fallocate {
     const CODE: u8 = Code::Fallocate as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.fd,
@@ -465,7 +828,7 @@ impl Op for Openat<'_> {
     const CODE: u8 = Code::Openat as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.dfd,
@@ -491,7 +854,7 @@ impl Op for Close {
     const CODE: u8 = Code::Close as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(Self::CODE, self.fd, ptr::null(), 0, 0)
     }
 }
@@ -506,7 +869,7 @@ impl Op for FilesUpdate<'_> {
     const CODE: u8 = Code::FilesUpdate as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             -1,
@@ -517,29 +880,89 @@ impl Op for FilesUpdate<'_> {
     }
 }
 
+// AT_ flags of statx(2)
+bitflags! {
+    pub struct AtFlags: u32 {
+        const EMPTY_PATH       = libc::AT_EMPTY_PATH as u32; // stat dfd itself if path is empty
+        const SYMLINK_NOFOLLOW = libc::AT_SYMLINK_NOFOLLOW as u32; // stat a symlink, not its target
+        const NO_AUTOMOUNT     = libc::AT_NO_AUTOMOUNT as u32; // don't trigger an automount
+        const FORCE_SYNC       = libc::AT_STATX_FORCE_SYNC as u32; // sync attributes with the server
+        const DONT_SYNC        = libc::AT_STATX_DONT_SYNC as u32; // don't sync attributes with the server
+    }
+}
+
+// STATX_ flags, the fields statx(2) is asked for
+bitflags! {
+    pub struct StatxMask: u32 {
+        const TYPE        = libc::STATX_TYPE;
+        const MODE        = libc::STATX_MODE;
+        const NLINK       = libc::STATX_NLINK;
+        const UID         = libc::STATX_UID;
+        const GID         = libc::STATX_GID;
+        const ATIME       = libc::STATX_ATIME;
+        const MTIME       = libc::STATX_MTIME;
+        const CTIME       = libc::STATX_CTIME;
+        const INO         = libc::STATX_INO;
+        const SIZE        = libc::STATX_SIZE;
+        const BLOCKS      = libc::STATX_BLOCKS;
+        const BASIC_STATS = libc::STATX_BASIC_STATS; // the fields of stat(2)
+        const BTIME       = libc::STATX_BTIME;
+        const MNT_ID      = libc::STATX_MNT_ID;
+        const DIOALIGN    = libc::STATX_DIOALIGN;
+    }
+}
+
+// Gets the attributes of `path` relative to `dfd`, as statx(2). The op owns
+// the buffer the kernel fills, so it is submitted with Driver::submit_owned
+// and read with `statx` once completed, see fs::Metadata::from_raw.
 #[derive(Debug)]
-pub struct Statx<'a> {
+pub struct Statx {
     pub dfd: RawFd,
-    pub path: &'a CStr,
-    pub flags: u32,
-    pub mask: u32,
-    pub statxbuf: &'a libc::statx,
+    pub path: CString,
+    pub flags: AtFlags,
+    pub mask: StatxMask,
+    pub statxbuf: Box<libc::statx>,
 }
 
-impl Op for Statx<'_> {
+impl Statx {
+    #[inline]
+    pub fn new(dfd: RawFd, path: CString, flags: AtFlags, mask: StatxMask) -> Self {
+        Self {
+            dfd,
+            path,
+            flags,
+            mask,
+            statxbuf: Box::new(unsafe { mem::zeroed() }),
+        }
+    }
+
+    // Gets the attributes of the open file `fd`, as fstat(2).
+    #[inline]
+    pub fn fd(fd: RawFd, mask: StatxMask) -> Self {
+        Self::new(fd, CString::default(), AtFlags::EMPTY_PATH, mask)
+    }
+
+    // The attributes filled by a successful completion
+    #[inline]
+    pub fn statx(&self) -> &libc::statx {
+        &self.statxbuf
+    }
+}
+
+impl Op for Statx {
     const CODE: u8 = Code::Statx as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.dfd,
             self.path.as_ptr() as *const _,
-            self.mask,
-            self.statxbuf as *const _ as u64,
+            self.mask.bits(),
+            &mut *self.statxbuf as *mut _ as u64,
         ) {
             Some(sqe) => {
-                sqe.set_statx_flags(self.flags);
+                sqe.set_statx_flags(self.flags.bits());
                 Some(sqe)
             }
             None => None,
@@ -558,11 +981,11 @@ impl Op for Read<'_> {
     const CODE: u8 = Code::Read as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.fd,
-            self.buf.as_ptr() as *const _,
+            self.buf.as_mut_ptr() as *const _,
             self.buf.len() as u32,
             self.offset,
         )
@@ -580,7 +1003,7 @@ impl Op for Write<'_> {
     const CODE: u8 = Code::Write as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.fd,
@@ -603,7 +1026,7 @@ impl Op for Fadvise {
     const CODE: u8 = Code::Fadvise as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.fd, ptr::null(), self.len, self.offset) {
             Some(sqe) => {
                 sqe.set_fadvise_advice_flags(self.advice as u32);
@@ -624,7 +1047,7 @@ impl Op for Madvise<'_> {
     const CODE: u8 = Code::Madvise as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             -1,
@@ -652,7 +1075,7 @@ impl Op for Send<'_> {
     const CODE: u8 = Code::Send as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.sockfd,
@@ -679,11 +1102,11 @@ impl Op for Recv<'_> {
     const CODE: u8 = Code::Recv as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.sockfd,
-            self.buf.as_ptr() as *const _,
+            self.buf.as_mut_ptr() as *const _,
             self.buf.len() as u32,
             0,
         ) {
@@ -696,6 +1119,55 @@ impl Op for Recv<'_> {
     }
 }
 
+// RESOLVE_ flags of openat2(2)
+bitflags! {
+    pub struct ResolveFlags: u64 {
+        const NO_XDEV       = 0x01; // no crossing of mount points
+        const NO_MAGICLINKS = 0x02; // no /proc/[pid]/fd style links
+        const NO_SYMLINKS   = 0x04; // no symlinks at all
+        const BENEATH       = 0x08; // no escaping the directory of dfd
+        const IN_ROOT       = 0x10; // treat the directory of dfd as /
+        const CACHED        = 0x20; // fail with -EAGAIN unless cached
+    }
+}
+
+// Receives into a buffer picked from the group `bgid` of ProvideBuffers,
+// whose id is given by cq::Entry::buffer_id. A multishot receive keeps
+// posting completions until the group runs out of buffers or an error
+// occurs.
+#[derive(Debug)]
+pub struct RecvSelect {
+    pub sockfd: RawFd,
+    pub len: u32,
+    pub bgid: u16,
+    pub flags: u32,
+    pub multishot: bool,
+}
+
+impl RecvSelect {
+    const MULTISHOT: u16 = 1 << 1;
+}
+
+impl Op for RecvSelect {
+    const CODE: u8 = Code::Recv as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(Self::CODE, self.sockfd, ptr::null(), self.len, 0) {
+            Some(sqe) => {
+                sqe.set_msg_flags(self.flags);
+                sqe.set_flags(BUFFER_SELECT);
+                sqe.set_buf_group(self.bgid);
+                if self.multishot {
+                    sqe.set_ioprio(Self::MULTISHOT);
+                }
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct OpenHow {
@@ -704,6 +1176,28 @@ pub struct OpenHow {
     pub resolve: u64,
 }
 
+impl OpenHow {
+    #[inline]
+    pub const fn new(flags: i32, mode: u32) -> Self {
+        Self {
+            flags: flags as u64,
+            mode: mode as u64,
+            resolve: 0,
+        }
+    }
+
+    #[inline]
+    pub const fn resolve(mut self, resolve: ResolveFlags) -> Self {
+        self.resolve = resolve.bits();
+        self
+    }
+
+    #[inline]
+    pub const fn resolve_flags(&self) -> ResolveFlags {
+        ResolveFlags::from_bits_truncate(self.resolve)
+    }
+}
+
 #[derive(Debug)]
 pub struct Openat2<'a> {
     pub dfd: RawFd,
@@ -715,7 +1209,7 @@ impl Op for Openat2<'_> {
     const CODE: u8 = Code::Openat2 as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.dfd,
@@ -738,7 +1232,7 @@ impl Op for EpollCtl<'_> {
     const CODE: u8 = Code::EpollCtl as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         sq.prep_rw(
             Self::CODE,
             self.epfd,
@@ -749,6 +1243,19 @@ impl Op for EpollCtl<'_> {
     }
 }
 
+// SPLICE_F_ flags, and SPLICE_F_FD_IN_FIXED of io_uring
+bitflags! {
+    pub struct SpliceFlags: u32 {
+        const MOVE        = 1 << 0; // move pages instead of copying
+        const NONBLOCK    = 1 << 1; // don't block on the pipe
+        const MORE        = 1 << 2; // more data will be coming
+        const GIFT        = 1 << 3; // pages passed in are a gift
+        const FD_IN_FIXED = 1 << 31; // fd_in is a fixed file index
+    }
+}
+
+// An offset of -1 uses and updates the file position; it must be -1 for
+// pipes.
 #[derive(Debug)]
 pub struct Splice {
     pub fd_in: RawFd,
@@ -756,14 +1263,14 @@ pub struct Splice {
     pub fd_out: RawFd,
     pub off_out: i64,
     pub nbytes: u32,
-    pub flags: u32,
+    pub flags: SpliceFlags,
 }
 
 impl Op for Splice {
     const CODE: u8 = Code::Splice as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.fd_out,
@@ -774,7 +1281,33 @@ impl Op for Splice {
             Some(sqe) => {
                 sqe.set_splice_off_in(self.off_in as u64);
                 sqe.set_splice_fd_in(self.fd_in);
-                sqe.set_splice_flags(self.flags);
+                sqe.set_splice_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Duplicates up to `nbytes` from the pipe `fd_in` to the pipe `fd_out`
+// without consuming them, as tee(2).
+#[derive(Debug)]
+pub struct Tee {
+    pub fd_in: RawFd,
+    pub fd_out: RawFd,
+    pub nbytes: u32,
+    pub flags: SpliceFlags,
+}
+
+impl Op for Tee {
+    const CODE: u8 = Code::Tee as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(Self::CODE, self.fd_out, ptr::null(), self.nbytes, 0) {
+            Some(sqe) => {
+                sqe.set_splice_fd_in(self.fd_in);
+                sqe.set_splice_flags(self.flags.bits());
                 Some(sqe)
             }
             None => None,
@@ -784,7 +1317,7 @@ impl Op for Splice {
 
 #[derive(Debug)]
 pub struct ProvideBuffers<'a> {
-    pub addr: &'a [u8],
+    pub addr: &'a mut [u8],
     pub nr: i32,
     pub bgid: u16,
     pub bid: u32,
@@ -794,11 +1327,11 @@ impl Op for ProvideBuffers<'_> {
     const CODE: u8 = Code::ProvideBuffers as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(
             Self::CODE,
             self.nr,
-            self.addr.as_ptr() as *const _,
+            self.addr.as_mut_ptr() as *const _,
             self.addr.len() as u32,
             self.bid as u64,
         ) {
@@ -821,7 +1354,7 @@ impl Op for RemoveBuffers {
     const CODE: u8 = Code::RemoveBuffers as u8;
 
     #[inline]
-    unsafe fn prepare<'a>(&self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
         match sq.prep_rw(Self::CODE, self.nr, ptr::null(), 0, 0) {
             Some(sqe) => {
                 sqe.set_buf_group(self.bgid);
@@ -831,3 +1364,389 @@ impl Op for RemoveBuffers {
         }
     }
 }
+
+// IORING_MSG_RING_ flags
+bitflags! {
+    pub struct MsgRingFlags: u32 {
+        const CQE_SKIP = 1 << 0; // don't post a cqe on the target ring
+    }
+}
+
+impl MsgRingFlags {
+    // sqe->addr commands of IORING_OP_MSG_RING
+    const MSG_DATA: usize = 0;
+    const MSG_SEND_FD: usize = 1;
+}
+
+// Posts a cqe with `res` and `user_data` on the `target` ring.
+#[derive(Debug)]
+pub struct MsgRing<'a> {
+    pub target: &'a RingHandle,
+    pub res: u32,
+    pub user_data: u64,
+    pub flags: MsgRingFlags,
+}
+
+impl Op for MsgRing<'_> {
+    const CODE: u8 = Code::MsgRing as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.target.as_raw_fd(),
+            MsgRingFlags::MSG_DATA as *const _,
+            self.res,
+            self.user_data,
+        ) {
+            Some(sqe) => {
+                sqe.set_msg_ring_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Installs the fixed file `src_index` of the submitting ring into slot
+// `dst_index` of the fixed files of the `target` ring, or a free slot if
+// `dst_index` is None, and posts a cqe with `user_data` on the target.
+#[derive(Debug)]
+pub struct MsgRingFd<'a> {
+    pub target: &'a RingHandle,
+    pub src_index: u32,
+    pub dst_index: Option<u32>,
+    pub user_data: u64,
+    pub flags: MsgRingFlags,
+}
+
+impl Op for MsgRingFd<'_> {
+    const CODE: u8 = Code::MsgRing as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.target.as_raw_fd(),
+            MsgRingFlags::MSG_SEND_FD as *const _,
+            0,
+            self.user_data,
+        ) {
+            Some(sqe) => {
+                sqe.set_addr3(self.src_index as u64);
+                sqe.set_file_index(self.dst_index);
+                sqe.set_msg_ring_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Creates a socket, as socket(2). SOCK_NONBLOCK and SOCK_CLOEXEC may be
+// or'ed into `ty`.
+#[derive(Debug)]
+pub struct Socket {
+    pub domain: i32,
+    pub ty: i32,
+    pub protocol: i32,
+}
+
+impl Op for Socket {
+    const CODE: u8 = Code::Socket as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        sq.prep_rw(
+            Self::CODE,
+            self.domain,
+            ptr::null(),
+            self.protocol as u32,
+            self.ty as u64,
+        )
+    }
+}
+
+// RENAME_ flags of renameat2(2)
+bitflags! {
+    pub struct RenameFlags: u32 {
+        const NOREPLACE = 1 << 0; // don't overwrite the target
+        const EXCHANGE  = 1 << 1; // exchange the source and the target
+        const WHITEOUT  = 1 << 2; // leave a whiteout object at the source
+    }
+}
+
+// Renames `old_path` relative to `old_dfd` to `new_path` relative to
+// `new_dfd`, as renameat2(2).
+#[derive(Debug)]
+pub struct RenameAt<'a> {
+    pub old_dfd: RawFd,
+    pub old_path: &'a CStr,
+    pub new_dfd: RawFd,
+    pub new_path: &'a CStr,
+    pub flags: RenameFlags,
+}
+
+impl Op for RenameAt<'_> {
+    const CODE: u8 = Code::RenameAt as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.old_dfd,
+            self.old_path.as_ptr() as *const _,
+            self.new_dfd as u32,
+            self.new_path.as_ptr() as u64,
+        ) {
+            Some(sqe) => {
+                sqe.set_rename_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// AT_ flags of unlinkat(2)
+bitflags! {
+    pub struct UnlinkFlags: u32 {
+        const REMOVEDIR = libc::AT_REMOVEDIR as u32; // remove a directory, as rmdir(2)
+    }
+}
+
+// Removes `path` relative to `dfd`, as unlinkat(2).
+#[derive(Debug)]
+pub struct UnlinkAt<'a> {
+    pub dfd: RawFd,
+    pub path: &'a CStr,
+    pub flags: UnlinkFlags,
+}
+
+impl Op for UnlinkAt<'_> {
+    const CODE: u8 = Code::UnlinkAt as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(Self::CODE, self.dfd, self.path.as_ptr() as *const _, 0, 0) {
+            Some(sqe) => {
+                sqe.set_unlink_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Creates the directory `path` relative to `dfd`, as mkdirat(2).
+#[derive(Debug)]
+pub struct MkdirAt<'a> {
+    pub dfd: RawFd,
+    pub path: &'a CStr,
+    pub mode: u32,
+}
+
+impl Op for MkdirAt<'_> {
+    const CODE: u8 = Code::MkdirAt as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        sq.prep_rw(
+            Self::CODE,
+            self.dfd,
+            self.path.as_ptr() as *const _,
+            self.mode,
+            0,
+        )
+    }
+}
+
+// Creates `link_path` relative to `new_dfd` as a symbolic link to
+// `target`, as symlinkat(2).
+#[derive(Debug)]
+pub struct SymlinkAt<'a> {
+    pub target: &'a CStr,
+    pub new_dfd: RawFd,
+    pub link_path: &'a CStr,
+}
+
+impl Op for SymlinkAt<'_> {
+    const CODE: u8 = Code::SymlinkAt as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        sq.prep_rw(
+            Self::CODE,
+            self.new_dfd,
+            self.target.as_ptr() as *const _,
+            0,
+            self.link_path.as_ptr() as u64,
+        )
+    }
+}
+
+// AT_ flags of linkat(2)
+bitflags! {
+    pub struct LinkFlags: u32 {
+        const SYMLINK_FOLLOW = libc::AT_SYMLINK_FOLLOW as u32; // dereference old_path if a symlink
+        const EMPTY_PATH     = libc::AT_EMPTY_PATH as u32; // link the file old_dfd refers to
+    }
+}
+
+// Creates `new_path` relative to `new_dfd` as a hard link to `old_path`
+// relative to `old_dfd`, as linkat(2).
+#[derive(Debug)]
+pub struct LinkAt<'a> {
+    pub old_dfd: RawFd,
+    pub old_path: &'a CStr,
+    pub new_dfd: RawFd,
+    pub new_path: &'a CStr,
+    pub flags: LinkFlags,
+}
+
+impl Op for LinkAt<'_> {
+    const CODE: u8 = Code::LinkAt as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.old_dfd,
+            self.old_path.as_ptr() as *const _,
+            self.new_dfd as u32,
+            self.new_path.as_ptr() as u64,
+        ) {
+            Some(sqe) => {
+                sqe.set_hardlink_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// XATTR_ flags of setxattr(2)
+bitflags! {
+    pub struct XattrFlags: u32 {
+        const CREATE  = 1 << 0; // fail if the attribute exists
+        const REPLACE = 1 << 1; // fail if the attribute does not exist
+    }
+}
+
+// The xattr ops own their buffers, so they are submitted with
+// Driver::submit_owned, which hands the op back with the cqe. The result
+// is the length of the value; getting with an empty `value` returns the
+// length without copying it.
+
+// Gets the attribute `name` of the file at `path`, as getxattr(2).
+#[derive(Debug)]
+pub struct GetXattr {
+    pub path: CString,
+    pub name: CString,
+    pub value: Vec<u8>,
+}
+
+impl Op for GetXattr {
+    const CODE: u8 = Code::GetXattr as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            0,
+            self.name.as_ptr() as *const _,
+            self.value.len() as u32,
+            self.value.as_mut_ptr() as u64,
+        ) {
+            Some(sqe) => {
+                sqe.set_addr3(self.path.as_ptr() as u64);
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Gets the attribute `name` of the open file `fd`, as fgetxattr(2).
+#[derive(Debug)]
+pub struct FGetXattr {
+    pub fd: RawFd,
+    pub name: CString,
+    pub value: Vec<u8>,
+}
+
+impl Op for FGetXattr {
+    const CODE: u8 = Code::FGetXattr as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        sq.prep_rw(
+            Self::CODE,
+            self.fd,
+            self.name.as_ptr() as *const _,
+            self.value.len() as u32,
+            self.value.as_mut_ptr() as u64,
+        )
+    }
+}
+
+// Sets the attribute `name` of the file at `path`, as setxattr(2).
+#[derive(Debug)]
+pub struct SetXattr {
+    pub path: CString,
+    pub name: CString,
+    pub value: Vec<u8>,
+    pub flags: XattrFlags,
+}
+
+impl Op for SetXattr {
+    const CODE: u8 = Code::SetXattr as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            0,
+            self.name.as_ptr() as *const _,
+            self.value.len() as u32,
+            self.value.as_ptr() as u64,
+        ) {
+            Some(sqe) => {
+                sqe.set_addr3(self.path.as_ptr() as u64);
+                sqe.set_xattr_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
+
+// Sets the attribute `name` of the open file `fd`, as fsetxattr(2).
+#[derive(Debug)]
+pub struct FSetXattr {
+    pub fd: RawFd,
+    pub name: CString,
+    pub value: Vec<u8>,
+    pub flags: XattrFlags,
+}
+
+impl Op for FSetXattr {
+    const CODE: u8 = Code::FSetXattr as u8;
+
+    #[inline]
+    unsafe fn prepare<'a>(&mut self, sq: &'a mut sq::Queue) -> Option<&'a mut sq::Entry> {
+        match sq.prep_rw(
+            Self::CODE,
+            self.fd,
+            self.name.as_ptr() as *const _,
+            self.value.len() as u32,
+            self.value.as_ptr() as u64,
+        ) {
+            Some(sqe) => {
+                sqe.set_xattr_flags(self.flags.bits());
+                Some(sqe)
+            }
+            None => None,
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::cmp;
 use std::io::Result;
 use std::mem::{self, MaybeUninit};
+use std::os::unix::io::AsRawFd;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use bitflags::bitflags;
 
+use crate::sys::{Backend, Kernel};
 use crate::uring::{Fd, Mmap, Uring};
-use crate::{cq, sq, sys};
+use crate::{cq, sq};
 
 // io_uring_setup() flags
 // IORING_SETUP_ flags
@@ -18,9 +21,19 @@ bitflags! {
         const CQSIZE    = 1 << 3; // app defines CQ size
         const CLAMP     = 1 << 4; // clamp SQ/CQ ring sizes
         const ATTACH_WQ = 1 << 5; // attach to existing wq
+        const SUBMIT_ALL    = 1 << 7;  // continue submit on error
+        const COOP_TASKRUN  = 1 << 8;  // no IPI to run task work
+        const SINGLE_ISSUER = 1 << 12; // only one task submits
     }
 }
 
+impl Setup {
+    // Hints a ring works without, dropped by UringBuilder::best_effort
+    // newest first when the kernel rejects them
+    pub(crate) const OPTIONAL: [Setup; 3] =
+        [Setup::SINGLE_ISSUER, Setup::COOP_TASKRUN, Setup::SUBMIT_ALL];
+}
+
 // UringParams->features flags
 bitflags! {
     pub struct Feat: u32 {
@@ -30,6 +43,13 @@ bitflags! {
         const RW_CUR_POS        = 1 << 3;
         const CUR_PERSONALITY   = 1 << 4;
         const FAST_POLL         = 1 << 5;
+        const POLL_32BITS       = 1 << 6;
+        const SQPOLL_NONFIXED   = 1 << 7;
+        const EXT_ARG           = 1 << 8;
+        const NATIVE_WORKERS    = 1 << 9;
+        const RSRC_TAGS         = 1 << 10;
+        const CQE_SKIP          = 1 << 11;
+        const LINKED_FILE       = 1 << 12;
     }
 }
 
@@ -38,23 +58,23 @@ bitflags! {
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct UringParams {
-    sq_entries: u32,
-    cq_entries: u32,
-    flags: u32, // IORING_SETUP_ flags (Setup::*)
+    pub(crate) sq_entries: u32,
+    pub(crate) cq_entries: u32,
+    pub(crate) flags: u32, // IORING_SETUP_ flags (Setup::*)
     sq_thread_cpu: u32,
     sq_thread_idle: u32,
-    features: u32, // Feat::* flags
+    pub(crate) features: u32, // Feat::* flags
     wq_fd: u32,
     _resv: [u32; 3],
-    sq_off: sq::Offsets,
-    cq_off: cq::Offsets,
+    pub(crate) sq_off: sq::Offsets,
+    pub(crate) cq_off: cq::Offsets,
 }
 
 impl UringParams {
     // Magic offsets for the application to mmap the data it needs
-    const IORING_OFF_SQ_RING: i64 = 0;
-    const IORING_OFF_CQ_RING: i64 = 0x800_0000;
-    const IORING_OFF_SQES: i64 = 0x1000_0000;
+    pub(crate) const IORING_OFF_SQ_RING: i64 = 0;
+    pub(crate) const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+    pub(crate) const IORING_OFF_SQES: i64 = 0x1000_0000;
 
     #[inline]
     pub fn flags(&self) -> Setup {
@@ -77,7 +97,11 @@ impl UringParams {
     }
 
     #[inline]
-    fn mmap<'a>(&self, fd: &Fd) -> Result<(sq::Queue<'a>, cq::Queue<'a>)> {
+    fn mmap<'a>(
+        &self,
+        fd: &Fd,
+        backend: &Rc<dyn Backend>,
+    ) -> Result<(sq::Queue<'a>, cq::Queue<'a>)> {
         let sq_ring_sz =
             self.sq_off.array() as usize + self.sq_entries as usize * mem::size_of::<u32>();
         let cq_ring_sz =
@@ -87,33 +111,36 @@ impl UringParams {
             let ring_sz = cmp::max(sq_ring_sz, cq_ring_sz);
             let sq_ring_ptr = Rc::new(Mmap::<libc::c_void>::try_new(
                 ring_sz,
-                &fd,
+                fd,
                 Self::IORING_OFF_SQ_RING,
+                backend,
             )?);
             let cq_ring_ptr = sq_ring_ptr.clone();
             (sq_ring_ptr, cq_ring_ptr)
         } else {
             let sq_ring_ptr = Rc::new(Mmap::<libc::c_void>::try_new(
                 sq_ring_sz,
-                &fd,
+                fd,
                 Self::IORING_OFF_SQ_RING,
+                backend,
             )?);
             let cq_ring_ptr = Rc::new(Mmap::<libc::c_void>::try_new(
                 cq_ring_sz,
-                &fd,
+                fd,
                 Self::IORING_OFF_CQ_RING,
+                backend,
             )?);
             (sq_ring_ptr, cq_ring_ptr)
         };
         let sqes_sz = self.sq_entries as usize * mem::size_of::<sq::Entry>();
-        let sqes = Mmap::<sq::Entry>::try_new(sqes_sz, &fd, Self::IORING_OFF_SQES)?;
+        let sqes = Mmap::<sq::Entry>::try_new(sqes_sz, fd, Self::IORING_OFF_SQES, backend)?;
         let sq = sq::Queue::new(sq_ring_ptr, sqes, self);
         let cq = cq::Queue::new(cq_ring_ptr, self);
         Ok((sq, cq))
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct UringBuilder {
     entries: u32,
     cq_entries: u32,
@@ -121,6 +148,8 @@ pub struct UringBuilder {
     sq_thread_cpu: u32,
     sq_thread_idle: u32,
     wq_fd: u32,
+    wq: Option<Arc<Fd>>,
+    best_effort: bool,
 }
 
 impl UringBuilder {
@@ -133,6 +162,8 @@ impl UringBuilder {
             sq_thread_cpu: 0,
             sq_thread_idle: 0,
             wq_fd: 0,
+            wq: None,
+            best_effort: false,
         }
     }
 
@@ -183,28 +214,112 @@ impl UringBuilder {
         self
     }
 
+    // Submits every sqe of a batch even if one fails to prepare, since 5.18.
+    #[inline]
+    pub fn submit_all(&mut self) -> &mut Self {
+        self.flags |= Setup::SUBMIT_ALL;
+        self
+    }
+
+    // Runs task work when the ring is entered rather than interrupting the
+    // task, since 5.19.
+    #[inline]
+    pub fn coop_taskrun(&mut self) -> &mut Self {
+        self.flags |= Setup::COOP_TASKRUN;
+        self
+    }
+
+    // Promises that only the task that built the ring submits to it, since
+    // 6.0.
+    #[inline]
+    pub fn single_issuer(&mut self) -> &mut Self {
+        self.flags |= Setup::SINGLE_ISSUER;
+        self
+    }
+
+    // Drops SUBMIT_ALL, COOP_TASKRUN and SINGLE_ISSUER if the kernel rejects
+    // them rather than failing; Uring::setup_flags tells which were applied.
+    #[inline]
+    pub fn best_effort(&mut self) -> &mut Self {
+        self.best_effort = true;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn setup(&mut self, flags: Setup) -> &mut Self {
+        self.flags |= flags;
+        self
+    }
+
+    // Attaches to the io-wq of `uring`. The built ring keeps `uring`'s file
+    // open, so the shared pool outlives every ring attached to it.
+    #[inline]
+    pub fn share_wq_with(&mut self, uring: &Uring<'_>) -> &mut Self {
+        self.share_wq(uring.shared_fd().clone())
+    }
+
+    #[inline]
+    pub(crate) fn share_wq(&mut self, wq: Arc<Fd>) -> &mut Self {
+        self.attach_wq(wq.as_raw_fd() as u32);
+        self.wq = Some(wq);
+        self
+    }
+
+    #[inline]
     pub fn try_build<'a>(&self) -> Result<Uring<'a>> {
-        let mut params = self.params();
-        let fd = self.setup(&mut params)?;
-        let (sq, cq) = params.mmap(&fd)?;
-        let uring = Uring::new(sq, cq, params.flags(), fd);
+        self.build_with(Rc::new(Kernel))
+    }
+
+    // Builds a ring run by an in-process Simulator instead of the kernel,
+    // returned with it to drive the completions.
+    #[cfg(feature = "simulator")]
+    pub fn simulate<'a>(&self) -> Result<(Uring<'a>, crate::Simulator)> {
+        let sim = crate::Simulator::new();
+        let uring = self.build_with(sim.backend())?;
+        Ok((uring, sim))
+    }
+
+    fn build_with<'a>(&self, backend: Rc<dyn Backend>) -> Result<Uring<'a>> {
+        let mut flags = self.flags;
+        let mut params = self.params(flags);
+        let requested = self.flags;
+        let mut optional = Setup::OPTIONAL
+            .iter()
+            .filter(|&&flag| requested.contains(flag));
+        let fd = loop {
+            match unsafe { backend.setup(self.entries, &mut params) } {
+                Ok(fd) => break Fd::new(fd),
+                Err(err) if self.best_effort && err.raw_os_error() == Some(libc::EINVAL) => {
+                    match optional.next() {
+                        Some(&flag) => flags.remove(flag),
+                        None => return Err(err),
+                    }
+                    params = self.params(flags);
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        let (sq, cq) = params.mmap(&fd, &backend)?;
+        let uring = Uring::new(
+            sq,
+            cq,
+            params.flags(),
+            params.features(),
+            fd,
+            self.wq.clone(),
+            backend,
+        );
         Ok(uring)
     }
 
     #[inline]
-    fn params(&self) -> UringParams {
+    fn params(&self, flags: Setup) -> UringParams {
         let mut params: UringParams = unsafe { MaybeUninit::zeroed().assume_init() };
         params.cq_entries = self.cq_entries;
-        params.flags = self.flags.bits();
+        params.flags = flags.bits();
         params.sq_thread_cpu = self.sq_thread_cpu;
         params.sq_thread_idle = self.sq_thread_idle;
         params.wq_fd = self.wq_fd;
         params
     }
-
-    #[inline]
-    fn setup(&self, params: &mut UringParams) -> Result<Fd> {
-        let fd = unsafe { sys::io_uring_setup(self.entries, params)? };
-        Ok(Fd::new(fd))
-    }
 }
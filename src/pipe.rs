@@ -0,0 +1,185 @@
+use std::cmp;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::driver::cvt;
+use crate::op::{self, SpliceFlags};
+use crate::uring::Fd;
+use crate::{sys, Driver};
+
+// Default capacity of a pipe on Linux
+const CHUNK: u32 = 64 * 1024;
+
+// An anonymous pipe, created with O_CLOEXEC.
+#[derive(Debug)]
+pub struct Pipe {
+    rd: Fd,
+    wr: Fd,
+}
+
+impl Pipe {
+    pub fn new() -> Result<Self> {
+        let mut fds = [0; 2];
+        sys::cvt(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })?;
+        Ok(Self {
+            rd: Fd::new(fds[0]),
+            wr: Fd::new(fds[1]),
+        })
+    }
+
+    // Sets the capacity of the pipe, which the kernel rounds up to a power
+    // of two pages. Returns the capacity set.
+    #[inline]
+    pub fn set_capacity(&self, capacity: u32) -> Result<u32> {
+        let ret = unsafe { libc::fcntl(self.wr.as_raw_fd(), libc::F_SETPIPE_SZ, capacity) };
+        sys::cvt(ret).map(|n| n as u32)
+    }
+
+    #[inline]
+    pub fn reader(&self) -> RawFd {
+        self.rd.as_raw_fd()
+    }
+
+    #[inline]
+    pub fn writer(&self) -> RawFd {
+        self.wr.as_raw_fd()
+    }
+}
+
+// Bytes moved by a Pipeline.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Totals {
+    // Spliced in from the source
+    pub read: u64,
+    // Spliced out to the destination
+    pub written: u64,
+    // Spliced out to the mirror of copy_tee
+    pub mirrored: u64,
+}
+
+// Copies between two fds through an intermediate pipe with splice, so the
+// data never reaches userspace. Either side may be a socket, a file or a
+// pipe.
+#[derive(Debug)]
+pub struct Pipeline {
+    driver: Driver,
+    pipe: Pipe,
+    chunk: u32,
+}
+
+impl Pipeline {
+    #[inline]
+    pub fn new(driver: &Driver) -> Result<Self> {
+        Self::with_chunk(driver, CHUNK)
+    }
+
+    // Moves up to `chunk` bytes per splice, the capacity of the pipe.
+    pub fn with_chunk(driver: &Driver, chunk: u32) -> Result<Self> {
+        let pipe = Pipe::new()?;
+        let chunk = pipe.set_capacity(chunk)?;
+        Ok(Self {
+            driver: driver.clone(),
+            pipe,
+            chunk,
+        })
+    }
+
+    // Copies from `from` to `to` until EOF.
+    //
+    // Each round splices into the pipe and, linked to it, out of the pipe,
+    // so a full chunk takes a single submission; short rounds are drained
+    // before the next one.
+    pub async fn copy(&self, from: RawFd, to: RawFd) -> Result<Totals> {
+        let mut totals = Totals::default();
+        loop {
+            let fill = splice(from, self.pipe.writer(), self.chunk, SpliceFlags::MOVE);
+            let drain = splice(
+                self.pipe.reader(),
+                to,
+                self.chunk,
+                SpliceFlags::MOVE | SpliceFlags::NONBLOCK,
+            );
+            let (fill, drain) = unsafe { self.driver.submit_linked(fill, drain, ()) };
+            let (fill, ()) = fill.await;
+            let (drain, ()) = drain.await;
+            let n = cvt(&fill)? as u64;
+            if n == 0 {
+                return Ok(totals);
+            }
+            totals.read += n;
+            match cvt(&drain) {
+                Ok(n) => totals.written += n as u64,
+                Err(err) if is_broken_link(&err) => {}
+                Err(err) => return Err(err),
+            }
+            totals.written += self
+                .drain(&self.pipe, to, totals.read - totals.written)
+                .await?;
+        }
+    }
+
+    // Copies from `from` to both `to` and `mirror` until EOF, duplicating
+    // each chunk into a second pipe with op::Tee.
+    pub async fn copy_tee(&self, from: RawFd, to: RawFd, mirror: RawFd) -> Result<Totals> {
+        let tee = Pipe::new()?;
+        tee.set_capacity(self.chunk)?;
+        let mut totals = Totals::default();
+        loop {
+            let fill = splice(from, self.pipe.writer(), self.chunk, SpliceFlags::MOVE);
+            let (fill, ()) = unsafe { self.driver.submit(fill, ()) }.await;
+            let n = cvt(&fill)?;
+            if n == 0 {
+                return Ok(totals);
+            }
+            totals.read += n as u64;
+            let op = op::Tee {
+                fd_in: self.pipe.reader(),
+                fd_out: tee.writer(),
+                nbytes: n,
+                flags: SpliceFlags::NONBLOCK,
+            };
+            let (cqe, ()) = unsafe { self.driver.submit(op, ()) }.await;
+            if cvt(&cqe)? != n {
+                return Err(Error::other("short tee"));
+            }
+            totals.mirrored += self.drain(&tee, mirror, n as u64).await?;
+            totals.written += self.drain(&self.pipe, to, n as u64).await?;
+        }
+    }
+
+    // Splices `len` bytes held by `pipe` out to `to`.
+    async fn drain(&self, pipe: &Pipe, to: RawFd, mut len: u64) -> Result<u64> {
+        let total = len;
+        while len > 0 {
+            let nbytes = cmp::min(len, self.chunk as u64) as u32;
+            let op = splice(pipe.reader(), to, nbytes, SpliceFlags::MOVE);
+            let (cqe, ()) = unsafe { self.driver.submit(op, ()) }.await;
+            match cvt(&cqe)? {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                n => len -= n as u64,
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[inline]
+fn splice(fd_in: RawFd, fd_out: RawFd, nbytes: u32, flags: SpliceFlags) -> op::Splice {
+    op::Splice {
+        fd_in,
+        off_in: -1,
+        fd_out,
+        off_out: -1,
+        nbytes,
+        flags,
+    }
+}
+
+// The linked splice did not run, or found the pipe empty
+#[inline]
+fn is_broken_link(err: &Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ECANCELED) | Some(libc::EAGAIN)
+    )
+}
@@ -0,0 +1,452 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Error, Result};
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::op::{self, Op};
+use crate::params::{Feat, Setup, UringParams};
+use crate::sys::{self, Backend};
+use crate::uring::{Enter, Probe, Uring};
+use crate::{cq, sq};
+
+// Picks the result of a submitted sqe, or None to leave it pending
+type Handler = Box<dyn FnMut(&sq::Entry) -> Option<i32>>;
+
+// A userspace stand-in for the kernel side of a ring, built with
+// UringBuilder::simulate. It consumes sqes from the SQ ring when the ring
+// enters it and posts cqes to the CQ ring only when told to, so tests
+// decide the order and results of completions.
+//
+// Sqes are completed by the handler set with `on_submit` as they are
+// submitted, or kept pending until `complete`. Links, timeouts and
+// multishot ops are not interpreted: every sqe is an opaque request. A
+// wait for more completions than are posted fails with EAGAIN, since
+// nothing else could post them. Setup flags other than CQSIZE and CLAMP
+// are rejected with EINVAL.
+#[derive(Clone)]
+pub struct Simulator {
+    kernel: Rc<SimKernel>,
+}
+
+impl Simulator {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self {
+            kernel: Rc::new(SimKernel {
+                state: RefCell::new(State::new()),
+            }),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn backend(&self) -> Rc<dyn Backend> {
+        self.kernel.clone()
+    }
+
+    // Sets the handler picking the result of each sqe as it is submitted,
+    // None leaving it pending. The default completes Nop with 0. The
+    // handler must not call into the Simulator.
+    pub fn on_submit<F>(&self, handler: F)
+    where
+        F: FnMut(&sq::Entry) -> Option<i32> + 'static,
+    {
+        self.kernel.state.borrow_mut().handler = Some(Box::new(handler));
+    }
+
+    // Fails the next sqe submitted with `errno`, before the handler sees it.
+    // Calls queue up, one per sqe.
+    #[inline]
+    pub fn fail_next(&self, errno: i32) {
+        self.kernel.state.borrow_mut().errors.push_back(errno);
+    }
+
+    // Fails the next io_uring_enter(2) with `errno` before it consumes any
+    // sqe. Calls queue up, one per enter.
+    #[inline]
+    pub fn fail_enter(&self, errno: i32) {
+        self.kernel.state.borrow_mut().enter_errors.push_back(errno);
+    }
+
+    // The sqes submitted and not completed yet, in submission order.
+    #[inline]
+    pub fn pending(&self) -> Vec<sq::Entry> {
+        self.kernel.state.borrow().pending.iter().copied().collect()
+    }
+
+    // Completes the pending sqe with `user_data`. Returns false if there is
+    // none.
+    pub fn complete(&self, user_data: u64, res: i32) -> bool {
+        let mut state = self.kernel.state.borrow_mut();
+        match state
+            .pending
+            .iter()
+            .position(|sqe| sqe.user_data() == user_data)
+        {
+            Some(i) => {
+                state.pending.remove(i);
+                state.post(cq::Entry::new(user_data, res, 0));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Completes the oldest pending sqe, returning it.
+    pub fn complete_next(&self, res: i32) -> Option<sq::Entry> {
+        let mut state = self.kernel.state.borrow_mut();
+        let sqe = state.pending.pop_front()?;
+        state.post(cq::Entry::new(sqe.user_data(), res, 0));
+        Some(sqe)
+    }
+
+    // Posts a cqe whether or not a pending sqe has `user_data`, for example
+    // one with IORING_CQE_F_MORE in `flags` for a multishot op.
+    #[inline]
+    pub fn post(&self, user_data: u64, res: i32, flags: u32) {
+        let cqe = cq::Entry::new(user_data, res, flags);
+        self.kernel.state.borrow_mut().post(cqe);
+    }
+
+    // Number of cqes held back because the CQ ring was full. They are moved
+    // to the ring when it is entered with GETEVENTS and has room.
+    #[inline]
+    pub fn overflowed(&self) -> usize {
+        self.kernel.state.borrow().overflow.len()
+    }
+
+    // Caps the cqes held back for a full CQ ring, as memory pressure would.
+    // Past `limit` cqes are dropped and counted in the CQ overflow counter,
+    // and submitting fails with EBUSY until the backlog drains. Unbounded
+    // by default.
+    #[inline]
+    pub fn set_backlog(&self, limit: usize) {
+        self.kernel.state.borrow_mut().backlog = limit;
+    }
+}
+
+impl fmt::Debug for Simulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.kernel, f)
+    }
+}
+
+struct SimKernel {
+    state: RefCell<State>,
+}
+
+impl fmt::Debug for SimKernel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("Simulator")
+            .field("pending", &state.pending.len())
+            .field("overflowed", &state.overflow.len())
+            .finish()
+    }
+}
+
+struct State {
+    // The SQ and CQ rings share one region, as with Feat::SINGLE_MMAP.
+    // u64 words keep the cqes aligned; the memory is only accessed through
+    // the pointers, as the ring does.
+    ring: Vec<u64>,
+    ring_ptr: *mut u8,
+    sqes: Vec<u64>,
+    sqes_ptr: *mut sq::Entry,
+    params: Option<UringParams>,
+    sq_head: u32,
+    cq_tail: u32,
+    pending: VecDeque<sq::Entry>,
+    overflow: VecDeque<cq::Entry>,
+    backlog: usize,
+    errors: VecDeque<i32>,
+    enter_errors: VecDeque<i32>,
+    handler: Option<Handler>,
+    eventfd: Option<RawFd>,
+}
+
+impl State {
+    const SQ_RING: u32 = 0;
+    const SQ_ARRAY: u32 = 64;
+
+    // IORING_SQ_CQ_OVERFLOW
+    const CQ_OVERFLOW: u32 = 1 << 1;
+
+    fn new() -> Self {
+        Self {
+            ring: Vec::new(),
+            ring_ptr: ptr::null_mut(),
+            sqes: Vec::new(),
+            sqes_ptr: ptr::null_mut(),
+            params: None,
+            sq_head: 0,
+            cq_tail: 0,
+            pending: VecDeque::new(),
+            overflow: VecDeque::new(),
+            backlog: usize::MAX,
+            errors: VecDeque::new(),
+            enter_errors: VecDeque::new(),
+            handler: None,
+            eventfd: None,
+        }
+    }
+
+    fn setup(&mut self, entries: u32, params: &mut UringParams) -> Result<()> {
+        let supported = Setup::CQSIZE | Setup::CLAMP;
+        if self.params.is_some() || entries == 0 || !supported.contains(params.flags()) {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        let sq_entries = entries.next_power_of_two();
+        let cq_entries = if params.flags().contains(Setup::CQSIZE) {
+            if params.cq_entries < sq_entries {
+                return Err(Error::from_raw_os_error(libc::EINVAL));
+            }
+            params.cq_entries.next_power_of_two()
+        } else {
+            sq_entries * 2
+        };
+
+        let cq_ring = align(Self::SQ_ARRAY + sq_entries * 4);
+        let cqes = cq_ring + 64;
+        let len = cqes as usize + cq_entries as usize * mem::size_of::<cq::Entry>();
+        self.ring = vec![0; len / 8];
+        self.ring_ptr = self.ring.as_mut_ptr() as *mut u8;
+        self.sqes = vec![0; sq_entries as usize * mem::size_of::<sq::Entry>() / 8];
+        self.sqes_ptr = self.sqes.as_mut_ptr() as *mut sq::Entry;
+
+        let sq_off = &mut params.sq_off;
+        sq_off.head = Self::SQ_RING;
+        sq_off.tail = Self::SQ_RING + 4;
+        sq_off.ring_mask = Self::SQ_RING + 8;
+        sq_off.ring_entries = Self::SQ_RING + 12;
+        sq_off.flags = Self::SQ_RING + 16;
+        sq_off.dropped = Self::SQ_RING + 20;
+        sq_off.array = Self::SQ_ARRAY;
+        let cq_off = &mut params.cq_off;
+        cq_off.head = cq_ring;
+        cq_off.tail = cq_ring + 4;
+        cq_off.ring_mask = cq_ring + 8;
+        cq_off.ring_entries = cq_ring + 12;
+        cq_off.overflow = cq_ring + 16;
+        cq_off.flags = cq_ring + 20;
+        cq_off.cqes = cqes;
+        params.sq_entries = sq_entries;
+        params.cq_entries = cq_entries;
+        params.features = (Feat::SINGLE_MMAP | Feat::NODROP).bits();
+
+        self.params = Some(*params);
+        self.word(params.sq_off.ring_mask)
+            .store(sq_entries - 1, Ordering::Relaxed);
+        self.word(params.sq_off.ring_entries)
+            .store(sq_entries, Ordering::Relaxed);
+        self.word(params.cq_off.ring_mask)
+            .store(cq_entries - 1, Ordering::Relaxed);
+        self.word(params.cq_off.ring_entries)
+            .store(cq_entries, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn enter(&mut self, to_submit: u32, min_complete: u32, flags: u32) -> Result<u32> {
+        let params = self.params()?;
+        let (sq_off, sq_entries) = (params.sq_off, params.sq_entries);
+        if let Some(errno) = self.enter_errors.pop_front() {
+            return Err(Error::from_raw_os_error(errno));
+        }
+        if to_submit > 0 && self.backlog_full() {
+            self.flush_overflow();
+            if self.backlog_full() {
+                return Err(Error::from_raw_os_error(libc::EBUSY));
+            }
+        }
+        let tail = self.word(sq_off.tail).load(Ordering::Acquire);
+        let mut submitted = 0;
+        while submitted < to_submit && self.sq_head != tail {
+            let slot = self.sq_head & (sq_entries - 1);
+            let index = unsafe {
+                *(self.ring_ptr.add(sq_off.array as usize) as *const u32).add(slot as usize)
+            };
+            let sqe = unsafe { ptr::read(self.sqes_ptr.add(index as usize)) };
+            self.sq_head = self.sq_head.wrapping_add(1);
+            self.word(sq_off.head)
+                .store(self.sq_head, Ordering::Release);
+            self.submit(sqe);
+            submitted += 1;
+        }
+
+        let flags = Enter::from_bits_truncate(flags);
+        if flags.contains(Enter::GETEVENTS) {
+            self.flush_overflow();
+            if self.ready() < min_complete {
+                return Err(Error::from_raw_os_error(libc::EAGAIN));
+            }
+        }
+        Ok(submitted)
+    }
+
+    fn submit(&mut self, sqe: sq::Entry) {
+        let res = match self.errors.pop_front() {
+            Some(errno) => Some(-errno),
+            None => match self.handler {
+                Some(ref mut handler) => handler(&sqe),
+                None if sqe.opcode() == op::Nop::CODE => Some(0),
+                None => None,
+            },
+        };
+        match res {
+            Some(res) => self.post(cq::Entry::new(sqe.user_data(), res, 0)),
+            None => self.pending.push_back(sqe),
+        }
+    }
+
+    fn post(&mut self, cqe: cq::Entry) {
+        let params = match self.params {
+            Some(params) => params,
+            None => return,
+        };
+        if !self.overflow.is_empty() || !self.push_cqe(&params, cqe) {
+            if self.overflow.len() < self.backlog {
+                self.overflow.push_back(cqe);
+                self.word(params.sq_off.flags)
+                    .fetch_or(Self::CQ_OVERFLOW, Ordering::Relaxed);
+            } else {
+                self.word(params.cq_off.overflow)
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if let Some(fd) = self.eventfd {
+            let one = 1u64;
+            unsafe { libc::write(fd, &one as *const _ as *const _, 8) };
+        }
+    }
+
+    fn push_cqe(&mut self, params: &UringParams, cqe: cq::Entry) -> bool {
+        let cq_off = params.cq_off;
+        let head = self.word(cq_off.head).load(Ordering::Acquire);
+        if self.cq_tail.wrapping_sub(head) == params.cq_entries {
+            return false;
+        }
+        let slot = self.cq_tail & (params.cq_entries - 1);
+        unsafe {
+            let cqes = self.ring_ptr.add(cq_off.cqes as usize) as *mut cq::Entry;
+            ptr::write(cqes.add(slot as usize), cqe);
+        }
+        self.cq_tail = self.cq_tail.wrapping_add(1);
+        self.word(cq_off.tail)
+            .store(self.cq_tail, Ordering::Release);
+        true
+    }
+
+    fn flush_overflow(&mut self) {
+        let params = match self.params {
+            Some(params) => params,
+            None => return,
+        };
+        while let Some(cqe) = self.overflow.front().copied() {
+            if !self.push_cqe(&params, cqe) {
+                return;
+            }
+            self.overflow.pop_front();
+        }
+        self.word(params.sq_off.flags)
+            .fetch_and(!Self::CQ_OVERFLOW, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn backlog_full(&self) -> bool {
+        !self.overflow.is_empty() && self.overflow.len() >= self.backlog
+    }
+
+    // Cqes posted to the CQ ring and not reaped yet
+    fn ready(&self) -> u32 {
+        match self.params {
+            Some(params) => {
+                let head = self.word(params.cq_off.head).load(Ordering::Acquire);
+                self.cq_tail.wrapping_sub(head)
+            }
+            None => 0,
+        }
+    }
+
+    #[inline]
+    fn params(&self) -> Result<UringParams> {
+        self.params
+            .ok_or_else(|| Error::from_raw_os_error(libc::EBADF))
+    }
+
+    #[inline]
+    fn word(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*(self.ring_ptr.add(offset as usize) as *const AtomicU32) }
+    }
+}
+
+impl Backend for SimKernel {
+    unsafe fn setup(&self, entries: u32, params: &mut UringParams) -> Result<RawFd> {
+        self.state.borrow_mut().setup(entries, params)?;
+        // A real fd to stand for the ring
+        sys::cvt(libc::eventfd(0, libc::EFD_CLOEXEC))
+    }
+
+    unsafe fn mmap(&self, len: usize, _fd: RawFd, offset: i64) -> Result<*mut libc::c_void> {
+        let state = self.state.borrow();
+        let (ptr, size) = match offset {
+            UringParams::IORING_OFF_SQ_RING | UringParams::IORING_OFF_CQ_RING => {
+                (state.ring_ptr, state.ring.len() * 8)
+            }
+            UringParams::IORING_OFF_SQES => (state.sqes_ptr as *mut u8, state.sqes.len() * 8),
+            _ => return Err(Error::from_raw_os_error(libc::EINVAL)),
+        };
+        if len > size {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        Ok(ptr as *mut _)
+    }
+
+    #[inline]
+    unsafe fn munmap(&self, _addr: *mut libc::c_void, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    unsafe fn enter(
+        &self,
+        _fd: RawFd,
+        to_submit: u32,
+        min_complete: u32,
+        flags: u32,
+        _sig: Option<&libc::sigset_t>,
+    ) -> Result<u32> {
+        self.state
+            .borrow_mut()
+            .enter(to_submit, min_complete, flags)
+    }
+
+    unsafe fn register(
+        &self,
+        _fd: RawFd,
+        opcode: u32,
+        arg: *const u8,
+        _nr_args: u32,
+    ) -> Result<u32> {
+        let mut state = self.state.borrow_mut();
+        match opcode {
+            Uring::REGISTER_PROBE => {
+                (*(arg as *mut Probe)).support_up_to(op::Code::LAST as u8);
+            }
+            Uring::REGISTER_EVENTFD | Uring::REGISTER_EVENTFD_ASYNC => {
+                state.eventfd = Some(*(arg as *const RawFd));
+            }
+            Uring::UNREGISTER_EVENTFD => state.eventfd = None,
+            // Buffers, files and the like are taken as registered
+            _ => {}
+        }
+        Ok(0)
+    }
+}
+
+#[inline]
+const fn align(offset: u32) -> u32 {
+    (offset + 63) & !63
+}
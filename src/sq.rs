@@ -3,7 +3,12 @@ use std::os::unix::io::RawFd;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicU32, Ordering};
 
+#[cfg(feature = "fault-injection")]
+use crate::fault::FaultInjector;
 use crate::params::UringParams;
+use crate::stats::OpCounts;
+#[cfg(feature = "tracing")]
+use crate::trace::{self, Tracer};
 use crate::uring::Mmap;
 
 // Filled with the offset for mmap(2)
@@ -11,13 +16,13 @@ use crate::uring::Mmap;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct Offsets {
-    head: u32,
-    tail: u32,
-    ring_mask: u32,
-    ring_entries: u32,
-    flags: u32, // IoRingSq::* flags
-    dropped: u32,
-    array: u32,
+    pub(crate) head: u32,
+    pub(crate) tail: u32,
+    pub(crate) ring_mask: u32,
+    pub(crate) ring_entries: u32,
+    pub(crate) flags: u32, // IoRingSq::* flags
+    pub(crate) dropped: u32,
+    pub(crate) array: u32,
     _resv1: u32,
     _resv2: u64,
 }
@@ -46,7 +51,12 @@ union OpFlags {
     open: libc::__u32,
     statx: libc::__u32,
     fadvise_advice: libc::__u32,
-    splice: libc::__u32, // SpliceFlags::*
+    splice: libc::__u32,   // SpliceFlags::*
+    msg_ring: libc::__u32, // MsgRingFlags::*
+    rename: libc::__u32,   // RenameFlags::*
+    unlink: libc::__u32,   // UnlinkFlags::*
+    hardlink: libc::__u32, // LinkFlags::*
+    xattr: libc::__u32,    // XattrFlags::*
 }
 
 impl fmt::Debug for OpFlags {
@@ -59,7 +69,7 @@ impl fmt::Debug for OpFlags {
 // IO submission data structure (Submission Queue Entry)
 // struct io_uring_sqe
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct Entry {
     opcode: u8,              // type of operation for this sqe
     flags: u8,               // IOSQE_ flags (IoSqe::*)
@@ -79,6 +89,56 @@ pub struct Entry {
 }
 
 impl Entry {
+    #[inline]
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    // IOSQE_ flags
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    #[inline]
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    #[inline]
+    pub fn user_data(&self) -> u64 {
+        self.user_data
+    }
+
+    // Pointer to the buffer, iovecs or other memory of the op
+    #[inline]
+    pub fn addr(&self) -> u64 {
+        self.addr_splice_off_in
+    }
+
+    // Buffer size, number of iovecs or another count of the op
+    #[allow(clippy::len_without_is_empty)]
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    // File offset, or a second pointer for ops such as Accept and Statx
+    #[inline]
+    pub fn off(&self) -> u64 {
+        self.off_addr2
+    }
+
+    #[inline]
+    pub(crate) fn set_flags(&mut self, flags: u8) {
+        self.flags |= flags;
+    }
+
+    #[inline]
+    pub(crate) fn set_ioprio(&mut self, ioprio: u16) {
+        self.ioprio = ioprio;
+    }
+
     #[inline]
     pub(crate) fn set_splice_off_in(&mut self, splice_off_in: u64) {
         self.addr_splice_off_in = splice_off_in;
@@ -153,11 +213,54 @@ impl Entry {
         self.op_flags.fadvise_advice = fadvise_advice_flags;
     }
 
+    #[inline]
+    pub(crate) fn set_msg_ring_flags(&mut self, msg_ring_flags: u32) {
+        self.op_flags.msg_ring = msg_ring_flags;
+    }
+
+    #[inline]
+    pub(crate) fn set_rename_flags(&mut self, rename_flags: u32) {
+        self.op_flags.rename = rename_flags;
+    }
+
+    #[inline]
+    pub(crate) fn set_unlink_flags(&mut self, unlink_flags: u32) {
+        self.op_flags.unlink = unlink_flags;
+    }
+
+    #[inline]
+    pub(crate) fn set_hardlink_flags(&mut self, hardlink_flags: u32) {
+        self.op_flags.hardlink = hardlink_flags;
+    }
+
+    #[inline]
+    pub(crate) fn set_xattr_flags(&mut self, xattr_flags: u32) {
+        self.op_flags.xattr = xattr_flags;
+    }
+
+    #[inline]
+    pub(crate) fn set_addr3(&mut self, addr3: u64) {
+        self._pad2[0] = addr3;
+    }
+
+    // Fixed file slot to install a file into, a free slot if None. Shares
+    // the field with splice_fd_in.
+    #[inline]
+    pub(crate) fn set_file_index(&mut self, file_index: Option<u32>) {
+        const FILE_INDEX_ALLOC: u32 = !0;
+        self.splice_fd_in = match file_index {
+            Some(index) => index + 1,
+            None => FILE_INDEX_ALLOC,
+        } as i32;
+    }
+
     #[inline]
     pub(crate) fn set_splice_flags(&mut self, splice_flags: u32) {
         self.op_flags.splice = splice_flags;
     }
 
+    // Values in the internal namespace of UserData are reserved for the crate,
+    // see UserData::is_internal.
     #[inline]
     pub fn set_user_data(&mut self, user_data: u64) {
         self.user_data = user_data;
@@ -181,7 +284,16 @@ pub struct Queue<'a> {
     sqe_head: u32,
     sqe_tail: u32,
 
+    // Sqes flushed to the SQ ring, in all and by opcode if counted
+    flushed: u64,
+    op_counts: Option<Box<OpCounts>>,
+
     ring_ptr: Rc<Mmap<libc::c_void>>,
+
+    #[cfg(feature = "fault-injection")]
+    pub(crate) faults: Option<FaultInjector>,
+    #[cfg(feature = "tracing")]
+    pub(crate) tracer: Tracer,
 }
 
 impl Queue<'_> {
@@ -225,7 +337,13 @@ impl Queue<'_> {
                 ktail_shadow,
                 sqe_head: 0,
                 sqe_tail: 0,
+                flushed: 0,
+                op_counts: None,
                 ring_ptr,
+                #[cfg(feature = "fault-injection")]
+                faults: None,
+                #[cfg(feature = "tracing")]
+                tracer: Tracer::new(kring_entries),
             }
         }
     }
@@ -235,6 +353,31 @@ impl Queue<'_> {
         self.kdropped.load(Ordering::Relaxed)
     }
 
+    #[inline]
+    pub fn entries(&self) -> u32 {
+        self.kring_entries
+    }
+
+    // Number of sqes submitted that the kernel has not consumed yet.
+    #[inline]
+    pub fn ready(&self) -> u32 {
+        self.ktail_shadow
+            .wrapping_sub(self.khead.load(Ordering::Acquire))
+    }
+
+    // Number of sqes prepared but not submitted yet.
+    #[inline]
+    pub fn unsubmitted(&self) -> u32 {
+        self.sqe_tail.wrapping_sub(self.sqe_head)
+    }
+
+    // Number of sqes that can be prepared before the ring is full.
+    #[inline]
+    pub fn space_left(&self) -> u32 {
+        let khead = self.khead.load(Ordering::Acquire);
+        self.kring_entries - self.sqe_tail.wrapping_sub(khead)
+    }
+
     #[inline]
     fn vacate_entry(&mut self) -> Option<&mut Entry> {
         if self.sqe_tail.wrapping_sub(self.khead_shadow) == self.kring_entries {
@@ -245,6 +388,8 @@ impl Queue<'_> {
         }
         let count = (self.sqe_tail & self.kring_mask) as usize;
         self.sqe_tail = self.sqe_tail.wrapping_add(1);
+        #[cfg(feature = "tracing")]
+        self.tracer.vacated(count);
         let entry = unsafe { &mut *(self.sqes.as_mut_ptr().add(count)) };
         Some(entry)
     }
@@ -274,6 +419,8 @@ impl Queue<'_> {
                 sqe.splice_fd_in = 0;
                 sqe._pad2[0] = 0;
                 sqe._pad2[1] = 0;
+                #[cfg(feature = "tracing")]
+                trace::prepared(opcode, fd);
                 Some(sqe)
             }
             None => None,
@@ -284,6 +431,27 @@ impl Queue<'_> {
     pub fn flush(&mut self) -> u32 {
         if self.sqe_head != self.sqe_tail {
             let to_submit = self.sqe_tail.wrapping_sub(self.sqe_head);
+            self.flushed += to_submit as u64;
+            // The sqes are only walked for what needs each of them
+            let walk = self.op_counts.is_some()
+                || cfg!(feature = "tracing")
+                || cfg!(feature = "fault-injection");
+            let mut i = if walk { self.sqe_head } else { self.sqe_tail };
+            while i != self.sqe_tail {
+                let slot = (i & self.kring_mask) as usize;
+                let sqe = unsafe { &*self.sqes.as_mut_ptr().add(slot) };
+                if let Some(counts) = &mut self.op_counts {
+                    counts[sqe.opcode() as usize] += 1;
+                }
+                #[cfg(feature = "tracing")]
+                self.tracer
+                    .submitted(slot, sqe.opcode(), sqe.fd(), sqe.user_data());
+                #[cfg(feature = "fault-injection")]
+                if let Some(faults) = &self.faults {
+                    faults.submitted(sqe.user_data(), sqe.opcode());
+                }
+                i = i.wrapping_add(1);
+            }
             self.sqe_head = self.sqe_tail;
 
             self.ktail_shadow = self.ktail_shadow.wrapping_add(to_submit);
@@ -304,6 +472,22 @@ impl Queue<'_> {
         (self.kflags.load(Ordering::Relaxed) & Self::CQ_OVERFLOW) != 0
     }
 
+    #[inline]
+    pub(crate) fn flushed(&self) -> u64 {
+        self.flushed
+    }
+
+    #[inline]
+    pub(crate) fn op_counts(&self) -> Option<&OpCounts> {
+        self.op_counts.as_deref()
+    }
+
+    // Starts counting the sqes flushed by opcode from zero, or stops.
+    #[inline]
+    pub(crate) fn count_ops(&mut self, on: bool) {
+        self.op_counts = if on { Some(Box::new([0; 256])) } else { None };
+    }
+
     #[inline]
     pub(crate) fn sqes(&self) -> &Mmap<Entry> {
         &self.sqes
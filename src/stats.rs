@@ -0,0 +1,80 @@
+use std::fmt;
+
+use crate::op::Code;
+
+// Counts indexed by opcode
+pub(crate) type OpCounts = [u64; 256];
+
+// A snapshot of the occupancy and counters of a ring, from Uring::stats.
+// Counters are cumulative since the ring was built.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub sq_entries: u32,
+    // Sqes submitted that the kernel has not consumed yet
+    pub sq_ready: u32,
+    // Sqes prepared but not submitted yet
+    pub sq_unsubmitted: u32,
+    // Sqes the kernel dropped as invalid
+    pub sq_dropped: u32,
+    pub cq_entries: u32,
+    // Cqes posted and not reaped yet
+    pub cq_ready: u32,
+    // Cqes the kernel dropped, see Uring::overflowed
+    pub cq_overflow: u32,
+    pub sqpoll_wakeups: u64,
+    // Sqes flushed to the SQ ring by submit, whether or not the kernel has
+    // consumed or accepted them yet
+    pub flushed: u64,
+    // Calls to io_uring_enter(2)
+    pub enters: u64,
+    // Cqes reaped, the crate's internal ones excluded
+    pub completed: u64,
+    pub(crate) ops: Box<OpCounts>,
+}
+
+impl Stats {
+    // Sqes of op `code` flushed to the SQ ring, 0 unless counted with
+    // Uring::count_ops.
+    #[inline]
+    pub fn flushed_op(&self, code: Code) -> u64 {
+        self.ops[code as usize]
+    }
+
+    // Ops flushed at least once, with the number of sqes; those unknown to
+    // the crate are left out.
+    #[inline]
+    pub fn ops(&self) -> impl Iterator<Item = (Code, u64)> + '_ {
+        self.ops
+            .iter()
+            .enumerate()
+            .filter(|(_, &n)| n > 0)
+            .filter_map(|(code, &n)| Code::from_u8(code as u8).map(|code| (code, n)))
+    }
+}
+
+impl fmt::Debug for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stats")
+            .field("sq_entries", &self.sq_entries)
+            .field("sq_ready", &self.sq_ready)
+            .field("sq_unsubmitted", &self.sq_unsubmitted)
+            .field("sq_dropped", &self.sq_dropped)
+            .field("cq_entries", &self.cq_entries)
+            .field("cq_ready", &self.cq_ready)
+            .field("cq_overflow", &self.cq_overflow)
+            .field("sqpoll_wakeups", &self.sqpoll_wakeups)
+            .field("flushed", &self.flushed)
+            .field("enters", &self.enters)
+            .field("completed", &self.completed)
+            .field("ops", &DebugOps(self))
+            .finish()
+    }
+}
+
+struct DebugOps<'a>(&'a Stats);
+
+impl fmt::Debug for DebugOps<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.0.ops()).finish()
+    }
+}
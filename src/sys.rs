@@ -1,10 +1,9 @@
+use std::fmt;
 use std::io::{Error, Result};
 use std::mem;
 use std::os::unix::io::RawFd;
 use std::ptr;
 
-use libc;
-
 use crate::params::UringParams;
 
 #[allow(non_upper_case_globals)]
@@ -16,6 +15,138 @@ const __NR_io_uring_enter: libc::c_long = 426;
 #[allow(non_upper_case_globals)]
 const __NR_io_uring_register: libc::c_long = 427;
 
+// The io_uring syscalls a Uring is built on, so that a ring can run against
+// the kernel or against the in-process Simulator.
+pub(crate) trait Backend: fmt::Debug {
+    // io_uring_setup(2), filling `params` with the ring layout
+    unsafe fn setup(&self, entries: u32, params: &mut UringParams) -> Result<RawFd>;
+
+    // Maps the part of the ring at the magic `offset` of UringParams
+    unsafe fn mmap(&self, len: usize, fd: RawFd, offset: i64) -> Result<*mut libc::c_void>;
+
+    unsafe fn munmap(&self, addr: *mut libc::c_void, len: usize) -> Result<()>;
+
+    // io_uring_enter(2)
+    unsafe fn enter(
+        &self,
+        fd: RawFd,
+        to_submit: u32,
+        min_complete: u32,
+        flags: u32,
+        sig: Option<&libc::sigset_t>,
+    ) -> Result<u32>;
+
+    // io_uring_enter(2) with IORING_ENTER_EXT_ARG, for rings with
+    // Feat::EXT_ARG
+    unsafe fn enter_ext(
+        &self,
+        _fd: RawFd,
+        _to_submit: u32,
+        _min_complete: u32,
+        _flags: u32,
+        _arg: &GeteventsArg,
+    ) -> Result<u32> {
+        Err(Error::from_raw_os_error(libc::EINVAL))
+    }
+
+    // io_uring_register(2)
+    unsafe fn register(&self, fd: RawFd, opcode: u32, arg: *const u8, nr_args: u32) -> Result<u32>;
+}
+
+// struct io_uring_getevents_arg
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    _pad: u32,
+    ts: u64,
+}
+
+impl GeteventsArg {
+    // _NSIG / 8, the size of the kernel's sigset_t
+    const SIGMASK_SZ: u32 = 8;
+
+    #[inline]
+    pub(crate) fn new(sig: Option<&libc::sigset_t>, ts: &libc::timespec) -> Self {
+        Self {
+            sigmask: sig.map_or(0, |sig| sig as *const _ as u64),
+            sigmask_sz: Self::SIGMASK_SZ,
+            _pad: 0,
+            ts: ts as *const _ as u64,
+        }
+    }
+}
+
+// The real syscalls.
+#[derive(Debug)]
+pub(crate) struct Kernel;
+
+impl Backend for Kernel {
+    #[inline]
+    unsafe fn setup(&self, entries: u32, params: &mut UringParams) -> Result<RawFd> {
+        io_uring_setup(entries, params)
+    }
+
+    #[inline]
+    unsafe fn mmap(&self, len: usize, fd: RawFd, offset: i64) -> Result<*mut libc::c_void> {
+        mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd,
+            offset,
+        )
+    }
+
+    #[inline]
+    unsafe fn munmap(&self, addr: *mut libc::c_void, len: usize) -> Result<()> {
+        munmap(addr, len)
+    }
+
+    #[inline]
+    unsafe fn enter(
+        &self,
+        fd: RawFd,
+        to_submit: u32,
+        min_complete: u32,
+        flags: u32,
+        sig: Option<&libc::sigset_t>,
+    ) -> Result<u32> {
+        match sig {
+            Some(sig) => io_uring_penter(fd, to_submit, min_complete, flags, sig),
+            None => io_uring_enter(fd, to_submit, min_complete, flags),
+        }
+    }
+
+    #[inline]
+    unsafe fn enter_ext(
+        &self,
+        fd: RawFd,
+        to_submit: u32,
+        min_complete: u32,
+        flags: u32,
+        arg: &GeteventsArg,
+    ) -> Result<u32> {
+        let n = libc::syscall(
+            __NR_io_uring_enter,
+            fd as libc::c_long,
+            to_submit as libc::c_long,
+            min_complete as libc::c_long,
+            flags as libc::c_long,
+            arg as *const GeteventsArg as libc::c_long,
+            mem::size_of::<GeteventsArg>() as libc::c_long,
+        ) as i32;
+        cvt(n).and(Ok(n as u32))
+    }
+
+    #[inline]
+    unsafe fn register(&self, fd: RawFd, opcode: u32, arg: *const u8, nr_args: u32) -> Result<u32> {
+        io_uring_register(fd, opcode, arg, nr_args)
+    }
+}
+
 #[inline]
 pub fn cvt(ret: i32) -> Result<i32> {
     if ret >= 0 {
@@ -43,7 +174,7 @@ pub unsafe fn io_uring_register(
     opcode: u32,
     arg: *const u8,
     nr_args: u32,
-) -> Result<()> {
+) -> Result<u32> {
     let ret = libc::syscall(
         __NR_io_uring_register,
         fd as libc::c_long,
@@ -51,7 +182,7 @@ pub unsafe fn io_uring_register(
         arg as libc::c_long,
         nr_args as libc::c_long,
     ) as libc::c_int;
-    cvt(ret).map(drop)
+    cvt(ret).map(|n| n as u32)
 }
 
 // int io_uring_enter(unsigned int fd, unsigned int to_submit, unsigned int min_complete, unsigned int flags, sigset_t *sig);
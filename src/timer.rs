@@ -0,0 +1,379 @@
+use std::cmp;
+use std::fmt;
+use std::io::{Error, Result};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use ruyi_slab::Slab;
+
+use crate::op::{self, Op};
+use crate::{cq, Uring, UserData};
+
+const LEVEL_BITS: u32 = 6;
+const SLOTS: usize = 1 << LEVEL_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+const NUM_LEVELS: usize = 6;
+
+// Largest number of ticks ahead of the wheel a timer can be scheduled
+const MAX_TICKS: u64 = (1 << (LEVEL_BITS * NUM_LEVELS as u32)) - 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TimerKey(usize);
+
+#[derive(Debug, Copy, Clone)]
+enum Kind {
+    // Posts a completion carrying user_data and -ETIME
+    Sleep,
+    // Cancels the in-flight request submitted with user_data
+    Deadline,
+}
+
+#[derive(Debug)]
+struct Timer {
+    when: u64,
+    user_data: u64,
+    kind: Kind,
+    level: usize,
+    slot: usize,
+}
+
+struct Level {
+    slots: [Vec<usize>; SLOTS],
+    occupied: u64,
+}
+
+impl Level {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            slots: [(); SLOTS].map(|_| Vec::new()),
+            occupied: 0,
+        }
+    }
+
+    // Returns the slot holding the next timers to expire on this level and
+    // the tick at which the slot starts.
+    fn next_expiration(&self, level: usize, elapsed: u64) -> Option<(usize, u64)> {
+        if self.occupied == 0 {
+            return None;
+        }
+        let shift = LEVEL_BITS * level as u32;
+        let now_slot = (elapsed >> shift) & SLOT_MASK;
+        let zeros = self.occupied.rotate_right(now_slot as u32).trailing_zeros() as u64;
+        let slot = (zeros + now_slot) & SLOT_MASK;
+
+        let slot_range = 1u64 << shift;
+        let level_range = slot_range << LEVEL_BITS;
+        let level_start = elapsed & !(level_range - 1);
+        let mut deadline = level_start + slot * slot_range;
+        if deadline < elapsed {
+            deadline += level_range;
+        }
+        Some((slot as usize, deadline))
+    }
+}
+
+// A hierarchical timer wheel of 6 levels of 64 slots.
+//
+// Any number of timers is driven by a single Timeout sqe, which is re-armed
+// to the earliest expiry as timers are added and fired. The completion of
+// that sqe carries the user_data given to `TimerWheel::new` and has to be
+// handed to `TimerWheel::process`.
+//
+// A sleep wakes up with a Timeout sqe of zero length carrying its user_data,
+// so that its completion reaches the CQ ring like any other.
+//
+// The sqes refer to timespecs owned by the wheel on the heap, which stay put
+// when the wheel moves. Those still in flight when the wheel drops are
+// leaked, since the kernel may yet read them.
+pub struct TimerWheel {
+    tick: Duration,
+    start: Instant,
+    elapsed: u64,
+    levels: Vec<Level>,
+    timers: Slab<Timer>,
+    user_data: u64,
+    armed: Option<u64>,
+    // Read by the kernel until the Timeout completes
+    timeout: Box<op::Timeout>,
+    // Updates prepared since the SQ ring was last seen drained, read by the
+    // kernel until it consumes their sqes. Boxed so that growing the Vec
+    // does not move them.
+    #[allow(clippy::vec_box)]
+    updates: Vec<Box<op::TimeoutUpdate>>,
+    // The zero Timeout of wakeups, read by the kernel until it consumes
+    // their sqes
+    wake: Box<op::Timeout>,
+    // Wakeups prepared since the SQ ring was last seen drained
+    waking: bool,
+}
+
+impl TimerWheel {
+    pub fn new(tick: Duration, user_data: UserData) -> Self {
+        assert!(tick > Duration::from_secs(0), "tick must be non-zero");
+        let start = Instant::now();
+        Self {
+            tick,
+            start,
+            elapsed: 0,
+            levels: (0..NUM_LEVELS).map(|_| Level::new()).collect(),
+            timers: Slab::new(),
+            user_data: user_data.into(),
+            armed: None,
+            timeout: Box::new(op::Timeout::at(start)),
+            updates: Vec::new(),
+            wake: Box::new(op::Timeout::after(Duration::from_secs(0))),
+            waking: false,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    // Posts a completion with `user_data` and -ETIME to the CQ ring once
+    // `deadline` has passed, found by `process` or `expire`.
+    #[inline]
+    pub fn sleep_until(
+        &mut self,
+        uring: &mut Uring<'_>,
+        deadline: Instant,
+        user_data: UserData,
+    ) -> Result<TimerKey> {
+        self.add(uring, deadline, user_data, Kind::Sleep)
+    }
+
+    // Cancels the request submitted with `user_data` if it is still in flight
+    // at `deadline`, so that it completes with -ECANCELED. The timer has to
+    // be canceled once the request completes on its own.
+    #[inline]
+    pub fn deadline(
+        &mut self,
+        uring: &mut Uring<'_>,
+        deadline: Instant,
+        user_data: UserData,
+    ) -> Result<TimerKey> {
+        self.add(uring, deadline, user_data, Kind::Deadline)
+    }
+
+    pub fn cancel(&mut self, key: TimerKey) -> bool {
+        match self.timers.remove(key.0) {
+            Some(timer) => {
+                let level = &mut self.levels[timer.level];
+                let slot = &mut level.slots[timer.slot];
+                slot.retain(|&k| k != key.0);
+                if slot.is_empty() {
+                    level.occupied &= !(1 << timer.slot);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Handles `cqe` if it is the completion of the wheel's Timeout sqe:
+    // fires the expired timers and re-arms the Timeout. Returns `false` for
+    // any other completion.
+    pub fn process(&mut self, uring: &mut Uring<'_>, cqe: &cq::Entry) -> Result<bool> {
+        if cqe.user_data() != self.user_data {
+            return Ok(false);
+        }
+        self.armed = None;
+        self.expire(uring)?;
+        self.arm(uring)?;
+        Ok(true)
+    }
+
+    // Fires the timers that have expired by now, preparing their sqes.
+    // Returns the number fired.
+    pub fn expire(&mut self, uring: &mut Uring<'_>) -> Result<usize> {
+        let now = self.ticks_floor(Instant::now());
+        let mut n = 0;
+        while let Some((level, slot, deadline)) = self.next_expiration() {
+            if deadline > now {
+                break;
+            }
+            self.elapsed = deadline;
+            self.levels[level].occupied &= !(1 << slot);
+            let keys = mem::take(&mut self.levels[level].slots[slot]);
+            for key in keys {
+                if self.timers[key].when <= deadline {
+                    let timer = self.timers.remove(key).unwrap();
+                    self.fire(uring, &timer)?;
+                    n += 1;
+                } else {
+                    self.insert(key);
+                }
+            }
+        }
+        if now > self.elapsed {
+            self.elapsed = now;
+        }
+        Ok(n)
+    }
+
+    fn add(
+        &mut self,
+        uring: &mut Uring<'_>,
+        deadline: Instant,
+        user_data: UserData,
+        kind: Kind,
+    ) -> Result<TimerKey> {
+        let when = self
+            .ticks_ceil(deadline)
+            .max(self.elapsed)
+            .min(self.elapsed + MAX_TICKS);
+        let key = self.timers.insert(Timer {
+            when,
+            user_data: user_data.into(),
+            kind,
+            level: 0,
+            slot: 0,
+        });
+        self.insert(key);
+        self.arm(uring)?;
+        Ok(TimerKey(key))
+    }
+
+    fn insert(&mut self, key: usize) {
+        let timer = &mut self.timers[key];
+        // Index of the highest bit that differs between elapsed and when,
+        // at least the bits of level 0. A timer MAX_TICKS ahead can differ
+        // above the top level once the wheel has advanced; it goes to the
+        // top level and is inserted again when its slot comes up.
+        let masked = (self.elapsed ^ timer.when) | SLOT_MASK;
+        let significant = 63 - masked.leading_zeros();
+        let level = cmp::min((significant / LEVEL_BITS) as usize, NUM_LEVELS - 1);
+        let slot = ((timer.when >> (LEVEL_BITS * level as u32)) & SLOT_MASK) as usize;
+        timer.level = level;
+        timer.slot = slot;
+        self.levels[level].slots[slot].push(key);
+        self.levels[level].occupied |= 1 << slot;
+    }
+
+    #[inline]
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        self.levels.iter().enumerate().find_map(|(i, level)| {
+            level
+                .next_expiration(i, self.elapsed)
+                .map(|(slot, deadline)| (i, slot, deadline))
+        })
+    }
+
+    fn fire(&mut self, uring: &mut Uring<'_>, timer: &Timer) -> Result<()> {
+        match timer.kind {
+            Kind::Sleep => {
+                Self::prepare(uring, &mut *self.wake, UserData::from(timer.user_data))?;
+                self.waking = true;
+            }
+            Kind::Deadline => {
+                let mut cancel = op::Cancel::user_data(timer.user_data);
+                Self::prepare(uring, &mut cancel, UserData::CANCEL)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Makes sure the Timeout sqe fires no later than the next expiration.
+    fn arm(&mut self, uring: &mut Uring<'_>) -> Result<()> {
+        // Every sqe prepared so far has been consumed, so have the updates
+        // and wakeups
+        let sq = uring.as_sq_mut();
+        if sq.space_left() == sq.entries() {
+            self.updates.clear();
+            self.waking = false;
+        }
+        let next = match self.next_expiration() {
+            Some((_, _, deadline)) => deadline,
+            None => return Ok(()),
+        };
+        match self.armed {
+            Some(armed) if armed <= next => {}
+            Some(_) => {
+                let mut update = Box::new(op::TimeoutUpdate {
+                    user_data: self.user_data,
+                    ts: self.timeout_at(next).ts,
+                    absolute: true,
+                    link: false,
+                });
+                Self::prepare(uring, &mut *update, UserData::TIMER)?;
+                self.updates.push(update);
+                self.armed = Some(next);
+            }
+            None => {
+                // The previous Timeout has completed, see process
+                *self.timeout = self.timeout_at(next);
+                Self::prepare(uring, &mut *self.timeout, UserData::from(self.user_data))?;
+                self.armed = Some(next);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn prepare<T: Op>(uring: &mut Uring<'_>, op: &mut T, user_data: UserData) -> Result<()> {
+        match unsafe { uring.prepare(op) } {
+            Some(sqe) => {
+                sqe.set_user_data(user_data.into());
+                Ok(())
+            }
+            None => Err(Error::from_raw_os_error(libc::EAGAIN)),
+        }
+    }
+
+    #[inline]
+    fn timeout_at(&self, tick: u64) -> op::Timeout {
+        let nanos = self.tick.as_nanos() * tick as u128;
+        op::Timeout::at(self.start + Duration::from_nanos(nanos as u64))
+    }
+
+    #[inline]
+    fn ticks_floor(&self, instant: Instant) -> u64 {
+        let dur = instant.saturating_duration_since(self.start);
+        (dur.as_nanos() / self.tick.as_nanos()) as u64
+    }
+
+    #[inline]
+    fn ticks_ceil(&self, instant: Instant) -> u64 {
+        let dur = instant.saturating_duration_since(self.start);
+        let tick = self.tick.as_nanos();
+        dur.as_nanos().div_ceil(tick) as u64
+    }
+}
+
+impl Drop for TimerWheel {
+    fn drop(&mut self) {
+        if self.armed.is_some() {
+            mem::forget(mem::replace(
+                &mut self.timeout,
+                Box::new(op::Timeout::at(self.start)),
+            ));
+        }
+        mem::forget(mem::take(&mut self.updates));
+        if self.waking {
+            mem::forget(mem::replace(
+                &mut self.wake,
+                Box::new(op::Timeout::at(self.start)),
+            ));
+        }
+    }
+}
+
+impl fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TimerWheel {{ tick: {:?}, elapsed: {}, timers: {}, armed: {:?} }}",
+            self.tick,
+            self.elapsed,
+            self.timers.len(),
+            self.armed
+        )
+    }
+}
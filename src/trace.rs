@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use tracing::Level;
+
+use crate::op::Code;
+use crate::{cq, UserData};
+
+// Latencies of an op from prepare to completion, in buckets of powers of
+// two nanoseconds.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Histogram {
+    // Bucket i counts latencies below 2^(i + 1) ns, and from 2^i ns on
+    buckets: [u64; 64],
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            buckets: [0; 64],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    #[inline]
+    fn record(&mut self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        let bucket = 63 - (nanos | 1).leading_zeros();
+        self.buckets[bucket as usize] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[inline]
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_nanos(0),
+            count => Duration::from_nanos(self.sum / count),
+        }
+    }
+
+    #[inline]
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    // An upper bound of the latency below which `q`, from 0 to 1, of the
+    // ops completed: the end of its bucket, or the max if lower.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank.max(1) {
+                let end = 1u64.checked_shl(bucket as u32 + 1).unwrap_or(u64::MAX);
+                return Duration::from_nanos(end.min(self.max));
+            }
+        }
+        self.max()
+    }
+
+    // The end of each bucket holding latencies, with their number.
+    #[inline]
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, &n)| n > 0)
+            .map(|(bucket, &n)| {
+                let end = 1u64.checked_shl(bucket as u32 + 1).unwrap_or(u64::MAX);
+                (Duration::from_nanos(end), n)
+            })
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("count", &self.count)
+            .field("mean", &self.mean())
+            .field("p50", &self.quantile(0.5))
+            .field("p99", &self.quantile(0.99))
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+// Emits the events of the `tracing` feature, correlating sqes and cqes
+// through their user data to tag completions with their op and fd. Only
+// does so while a subscriber wants the events, or latencies are recorded,
+// see Uring::record_latency.
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    // Whether latencies are recorded
+    latency: bool,
+    // When the sqe in each SQ slot was prepared, while latencies are
+    // recorded
+    prepared: Box<[Instant]>,
+    // The ops submitted, by user data
+    in_flight: HashMap<u64, InFlight>,
+    histograms: HashMap<u8, Histogram>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct InFlight {
+    opcode: u8,
+    fd: i32,
+    prepared: Option<Instant>,
+}
+
+impl Tracer {
+    #[inline]
+    pub(crate) fn new(entries: u32) -> Self {
+        Self {
+            latency: false,
+            prepared: vec![Instant::now(); entries as usize].into_boxed_slice(),
+            in_flight: HashMap::new(),
+            histograms: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn set_latency(&mut self, latency: bool) {
+        self.latency = latency;
+    }
+
+    // An sqe is being prepared in `slot`
+    #[inline]
+    pub(crate) fn vacated(&mut self, slot: usize) {
+        if self.latency {
+            self.prepared[slot] = Instant::now();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn submitted(&mut self, slot: usize, opcode: u8, fd: i32, user_data: u64) {
+        tracing::trace!(op = ?OpName(opcode), fd, user_data, "submit");
+        if !(self.latency || tracing::enabled!(Level::TRACE))
+            || UserData::from(user_data).is_internal()
+        {
+            return;
+        }
+        let prepared = if self.latency {
+            Some(self.prepared[slot])
+        } else {
+            None
+        };
+        let op = InFlight {
+            opcode,
+            fd,
+            prepared,
+        };
+        self.in_flight.insert(user_data, op);
+    }
+
+    pub(crate) fn completed(&mut self, cqe: &cq::Entry) {
+        if self.in_flight.is_empty() {
+            tracing::trace!(user_data = cqe.user_data(), res = cqe.res(), "complete");
+            return;
+        }
+        let user_data = cqe.user_data();
+        let op = if cqe.more() {
+            self.in_flight.get(&user_data).copied()
+        } else {
+            self.in_flight.remove(&user_data)
+        };
+        match op {
+            Some(op) => {
+                let latency = op.prepared.map(|prepared| prepared.elapsed());
+                if let Some(latency) = latency {
+                    self.histograms
+                        .entry(op.opcode)
+                        .or_insert_with(Histogram::new)
+                        .record(latency);
+                }
+                tracing::trace!(
+                    op = ?OpName(op.opcode),
+                    fd = op.fd,
+                    user_data,
+                    res = cqe.res(),
+                    ?latency,
+                    "complete"
+                );
+            }
+            None => tracing::trace!(user_data, res = cqe.res(), "complete"),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn histograms(&self) -> &HashMap<u8, Histogram> {
+        &self.histograms
+    }
+}
+
+#[inline]
+pub(crate) fn prepared(opcode: u8, fd: i32) {
+    tracing::trace!(op = ?OpName(opcode), fd, "prepare");
+}
+
+// The op of an opcode in events, its number if unknown to the crate
+struct OpName(u8);
+
+impl fmt::Debug for OpName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Code::from_u8(self.0) {
+            Some(code) => fmt::Debug::fmt(&code, f),
+            None => fmt::Debug::fmt(&self.0, f),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::io::Result;
+
+use ruyi_slab::Slab;
+
+use crate::op::Code;
+use crate::{cq, Uring};
+
+// Layout of the 64-bit user_data carried from an sqe to its cqe:
+//
+//   63..62  tag
+//   61..0   payload
+//
+//   tag 0b00: pointer, payload is the address (user space addresses never
+//             reach bit 62)
+//   tag 0b01: slab key
+//   tag 0b10: opcode in bits 61..54, index in bits 53..0
+//   tag 0b11: free for raw values, except the 16 highest, which are
+//             reserved for sqes generated by this crate
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct UserData(u64);
+
+impl UserData {
+    const TAG_SHIFT: u32 = 62;
+    const PAYLOAD_MASK: u64 = (1 << Self::TAG_SHIFT) - 1;
+
+    const TAG_PTR: u64 = 0b00;
+    const TAG_KEY: u64 = 0b01;
+    const TAG_OP: u64 = 0b10;
+
+    // Lowest value reserved for sqes generated by this crate
+    const INTERNAL_MIN: u64 = u64::MAX - 15;
+
+    const OP_SHIFT: u32 = 54;
+    const INDEX_MASK: u64 = (1 << Self::OP_SHIFT) - 1;
+
+    /// Largest slab key that can be encoded.
+    pub const MAX_KEY: usize = Self::PAYLOAD_MASK as usize;
+
+    /// Largest index that can be encoded alongside an opcode.
+    pub const MAX_INDEX: u64 = Self::INDEX_MASK;
+
+    // Timeout sqes submitted by `Uring::wait_cqes`.
+    pub(crate) const TIMEOUT: Self = Self(u64::MAX);
+    // Cancel sqes submitted by `Uring::cancel_fd` and `Uring::cancel_all`.
+    pub(crate) const CANCEL: Self = Self(u64::MAX - 1);
+    // Timeout updates submitted by `TimerWheel`.
+    pub(crate) const TIMER: Self = Self(u64::MAX - 2);
+
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    #[inline]
+    pub const fn into_raw(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        let addr = ptr as usize as u64;
+        assert!(addr >> Self::TAG_SHIFT == 0, "pointer out of range");
+        Self(addr)
+    }
+
+    #[inline]
+    pub fn from_key(key: usize) -> Self {
+        assert!(key <= Self::MAX_KEY, "key out of range");
+        Self(Self::TAG_KEY << Self::TAG_SHIFT | key as u64)
+    }
+
+    #[inline]
+    pub fn from_op(code: Code, index: u64) -> Self {
+        assert!(index <= Self::MAX_INDEX, "index out of range");
+        Self(Self::TAG_OP << Self::TAG_SHIFT | (code as u64) << Self::OP_SHIFT | index)
+    }
+
+    #[inline]
+    const fn tag(&self) -> u64 {
+        self.0 >> Self::TAG_SHIFT
+    }
+
+    #[inline]
+    pub fn as_ptr<T>(&self) -> Option<*const T> {
+        if self.tag() == Self::TAG_PTR {
+            Some(self.0 as usize as *const T)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn key(&self) -> Option<usize> {
+        if self.tag() == Self::TAG_KEY {
+            Some((self.0 & Self::PAYLOAD_MASK) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the op and index encoded by `from_op`, None if the opcode is
+    /// unknown to the crate.
+    #[inline]
+    pub fn op(&self) -> Option<(Code, u64)> {
+        if self.tag() == Self::TAG_OP {
+            let code = ((self.0 & Self::PAYLOAD_MASK) >> Self::OP_SHIFT) as u8;
+            Code::from_u8(code).map(|code| (code, self.0 & Self::INDEX_MASK))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the value is one of the 16 highest, reserved for
+    /// sqes generated by this crate, such as timeouts and cancels.
+    ///
+    /// Completions carrying such values are consumed by the crate and never
+    /// returned to the caller, so they must not be used for user sqes.
+    #[inline]
+    pub const fn is_internal(&self) -> bool {
+        self.0 >= Self::INTERNAL_MIN
+    }
+}
+
+impl From<u64> for UserData {
+    #[inline]
+    fn from(raw: u64) -> Self {
+        Self(raw)
+    }
+}
+
+impl From<UserData> for u64 {
+    #[inline]
+    fn from(user_data: UserData) -> Self {
+        user_data.0
+    }
+}
+
+impl fmt::Debug for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.tag() {
+            Self::TAG_PTR => write!(f, "UserData::Ptr({:#x})", self.0),
+            Self::TAG_KEY => write!(f, "UserData::Key({})", self.0 & Self::PAYLOAD_MASK),
+            Self::TAG_OP => match self.op() {
+                Some((code, index)) => write!(f, "UserData::Op({:?}, {})", code, index),
+                None => write!(f, "UserData::Raw({:#x})", self.0),
+            },
+            _ if self.is_internal() => write!(f, "UserData::Internal({:#x})", self.0),
+            _ => write!(f, "UserData::Raw({:#x})", self.0),
+        }
+    }
+}
+
+pub trait Handler {
+    fn handle(&mut self, cqe: &cq::Entry);
+}
+
+impl<F: FnMut(&cq::Entry)> Handler for F {
+    #[inline]
+    fn handle(&mut self, cqe: &cq::Entry) {
+        self(cqe)
+    }
+}
+
+type OpHandler<'h> = Box<dyn FnMut(u64, &cq::Entry) + 'h>;
+type PtrHandler<'h> = Box<dyn FnMut(*const (), &cq::Entry) + 'h>;
+
+/// Routes completions to handlers according to the UserData they carry:
+/// slab keys to handlers added by `register`, opcodes to handlers added by
+/// `register_op` and pointers to the handler added by `register_ptr`.
+pub struct Dispatcher<'h> {
+    handlers: Slab<Box<dyn Handler + 'h>>,
+    op_handlers: Vec<Option<OpHandler<'h>>>,
+    ptr_handler: Option<PtrHandler<'h>>,
+}
+
+impl<'h> Dispatcher<'h> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            handlers: Slab::new(),
+            op_handlers: Vec::new(),
+            ptr_handler: None,
+        }
+    }
+
+    /// Registers a handler and returns the UserData to put on the sqes it
+    /// should receive. The handler stays registered until `unregister`, so
+    /// it can serve multishot requests.
+    #[inline]
+    pub fn register<H: Handler + 'h>(&mut self, handler: H) -> UserData {
+        UserData::from_key(self.handlers.insert(Box::new(handler)))
+    }
+
+    #[inline]
+    pub fn unregister(&mut self, user_data: UserData) -> bool {
+        match user_data.key() {
+            Some(key) => self.handlers.remove(key).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn register_op<F>(&mut self, code: Code, handler: F)
+    where
+        F: FnMut(u64, &cq::Entry) + 'h,
+    {
+        let i = code as usize;
+        if self.op_handlers.len() <= i {
+            self.op_handlers.resize_with(i + 1, || None);
+        }
+        self.op_handlers[i] = Some(Box::new(handler));
+    }
+
+    #[inline]
+    pub fn register_ptr<F>(&mut self, handler: F)
+    where
+        F: FnMut(*const (), &cq::Entry) + 'h,
+    {
+        self.ptr_handler = Some(Box::new(handler));
+    }
+
+    /// Routes `cqe` to its handler. Returns `false` if no handler matches.
+    pub fn dispatch(&mut self, cqe: &cq::Entry) -> bool {
+        let user_data = UserData::from(cqe.user_data());
+        if let Some(key) = user_data.key() {
+            if let Some(handler) = self.handlers.get_mut(key) {
+                handler.handle(cqe);
+                return true;
+            }
+        } else if let Some((code, index)) = user_data.op() {
+            if let Some(Some(handler)) = self.op_handlers.get_mut(code as usize) {
+                handler(index, cqe);
+                return true;
+            }
+        } else if let Some(ptr) = user_data.as_ptr::<()>() {
+            match self.ptr_handler {
+                Some(ref mut handler) if !ptr.is_null() => {
+                    handler(ptr, cqe);
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Dispatches every completion currently in the CQ ring without
+    /// entering the kernel. Returns the number of completions consumed.
+    #[inline]
+    pub fn drain(&mut self, uring: &mut Uring<'_>) -> Result<u32> {
+        uring.reap(|cqe| {
+            self.dispatch(&cqe);
+        })
+    }
+}
+
+impl Default for Dispatcher<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Dispatcher<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Dispatcher {{ handlers: {}, op_handlers: {}, ptr_handler: {} }}",
+            self.handlers.len(),
+            self.op_handlers.iter().filter(|h| h.is_some()).count(),
+            self.ptr_handler.is_some()
+        )
+    }
+}
@@ -1,15 +1,21 @@
 use std::alloc::{alloc_zeroed, Layout};
+use std::cmp;
 use std::fmt;
+use std::fs;
 use std::io::{Error, IoSliceMut, Result};
+use std::mem;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 
 use bitflags::bitflags;
 
-use crate::op::{self, Op};
-use crate::params::{Setup, UringBuilder};
-use crate::{cq, sq, sys};
+use crate::op::{self, Cancel, Code, Op, SyncCancelReg};
+use crate::params::{Feat, Setup, UringBuilder};
+use crate::sys::{self, Backend, GeteventsArg};
+use crate::{cq, sq, FdInfo, Stats, UserData};
 
 #[derive(Debug)]
 pub(crate) struct Fd(RawFd);
@@ -37,27 +43,45 @@ impl Drop for Fd {
     }
 }
 
+// A reference to a ring that can be sent to other threads, for example to
+// target it with op::MsgRing. The ring's file stays open while any handle
+// exists.
+#[derive(Debug, Clone)]
+pub struct RingHandle(Arc<Fd>);
+
+impl AsRawFd for RingHandle {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsRawFd for Uring<'_> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Mmap<T> {
     addr: ptr::NonNull<T>,
     len: usize,
+    backend: Rc<dyn Backend>,
 }
 
 impl<T> Mmap<T> {
     #[inline]
-    pub fn try_new(len: usize, fd: &Fd, offset: i64) -> Result<Self> {
+    pub fn try_new(len: usize, fd: &Fd, offset: i64, backend: &Rc<dyn Backend>) -> Result<Self> {
         let addr = unsafe {
-            let ptr = sys::mmap(
-                ptr::null_mut(),
-                len,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED | libc::MAP_POPULATE,
-                fd.as_raw_fd(),
-                offset,
-            )?;
+            let ptr = backend.mmap(len, fd.as_raw_fd(), offset)?;
             ptr::NonNull::new_unchecked(ptr as *mut T)
         };
-        Ok(Self { addr, len })
+        Ok(Self {
+            addr,
+            len,
+            backend: backend.clone(),
+        })
     }
 
     #[inline]
@@ -75,7 +99,9 @@ impl<T> Drop for Mmap<T> {
     #[inline]
     fn drop(&mut self) {
         unsafe {
-            sys::munmap(self.addr.as_ptr() as *mut _, self.len).ok();
+            self.backend
+                .munmap(self.addr.as_ptr() as *mut _, self.len)
+                .ok();
         }
     }
 }
@@ -111,26 +137,85 @@ pub struct Probe {
 }
 
 impl Probe {
+    const SUPPORTED: u16 = 1 << 0;
+
     #[inline]
     pub fn support<T: Op>(&self) -> bool {
-        const SUPPORTED: u16 = 1 << 0;
-        if T::CODE <= self.last_op {
-            let probe_op = unsafe { self.ops.get_unchecked(T::CODE as usize) };
-            probe_op.flags & SUPPORTED != 0
+        self.support_opcode(T::CODE)
+    }
+
+    #[inline]
+    pub fn support_code(&self, code: Code) -> bool {
+        self.support_opcode(code as u8)
+    }
+
+    // The ops known to the crate that the kernel supports.
+    #[inline]
+    pub fn supported_ops(&self) -> impl Iterator<Item = Code> + '_ {
+        (0..=Code::LAST as u8)
+            .filter(move |&opcode| self.support_opcode(opcode))
+            .filter_map(Code::from_u8)
+    }
+
+    #[inline]
+    fn support_opcode(&self, opcode: u8) -> bool {
+        if opcode <= self.last_op && opcode < self.ops_len {
+            let probe_op = unsafe { self.ops.get_unchecked(opcode as usize) };
+            probe_op.flags & Self::SUPPORTED != 0
         } else {
             false
         }
     }
+
+    // Marks every op up to `last_op` as supported, as the Simulator does
+    #[cfg(feature = "simulator")]
+    #[inline]
+    pub(crate) fn support_up_to(&mut self, last_op: u8) {
+        self.last_op = last_op;
+        self.ops_len = last_op + 1;
+        for (code, probe_op) in self.ops[..=last_op as usize].iter_mut().enumerate() {
+            probe_op.op = code as u8;
+            probe_op.flags = Self::SUPPORTED;
+        }
+    }
 }
 
 impl fmt::Debug for Probe {
-    #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Probe {{ last_op: {}, ops_len: {} }}",
-            self.last_op, self.ops_len
-        )
+        f.debug_struct("Probe")
+            .field("last_op", &self.last_op)
+            .field("ops_len", &self.ops_len)
+            .field("supported", &DebugOps(self))
+            .finish()
+    }
+}
+
+struct DebugOps<'a>(&'a Probe);
+
+impl fmt::Debug for DebugOps<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.supported_ops()).finish()
+    }
+}
+
+// A table of the opcodes known to the crate or to the kernel, whichever
+// knows more, and whether the kernel supports them.
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>6}  {:<16}supported", "opcode", "op")?;
+        for opcode in 0..=cmp::max(self.last_op, Code::LAST as u8) {
+            let name = match Code::from_u8(opcode) {
+                Some(code) => format!("{:?}", code),
+                None => "?".to_owned(),
+            };
+            let supported = if self.support_opcode(opcode) {
+                "yes"
+            } else {
+                "no"
+            };
+            writeln!(f, "{:>6}  {:<16}{}", opcode, name, supported)?;
+        }
+        Ok(())
     }
 }
 
@@ -145,8 +230,12 @@ pub struct Restriction {
 
 impl Restriction {
     const REGISTER_OP: u16 = 0;
+    // For the sqe constructors below, not implemented yet
+    #[allow(dead_code)]
     const SQE_OP: u16 = 1;
+    #[allow(dead_code)]
     const SQE_FLAGS_ALLOWED: u16 = 2;
+    #[allow(dead_code)]
     const SQE_FLAGS_REQUIRED: u16 = 3;
 
     #[inline]
@@ -180,6 +269,8 @@ bitflags! {
     pub struct Enter: u32 {
         const GETEVENTS = 1 << 0;
         const SQ_WAKEUP = 1 << 1;
+        const SQ_WAIT   = 1 << 2;
+        const EXT_ARG   = 1 << 3;
     }
 }
 
@@ -188,8 +279,15 @@ pub struct Uring<'a> {
     sq: sq::Queue<'a>,
     cq: cq::Queue<'a>,
     flags: Setup,
-    fd: Fd,
-    ts: libc::timespec,
+    features: Feat,
+    fd: Arc<Fd>,
+    // Ring whose io-wq this ring is attached to
+    wq: Option<Arc<Fd>>,
+    timeout: op::Timeout,
+    sqpoll_wakeups: u64,
+    enters: u64,
+    completed: u64,
+    backend: Rc<dyn Backend>,
 }
 
 impl<'a> Uring<'a> {
@@ -198,27 +296,42 @@ impl<'a> Uring<'a> {
     const UNREGISTER_BUFFERS: libc::c_uint = 1;
     const REGISTER_FILES: libc::c_uint = 2;
     const UNREGISTER_FILES: libc::c_uint = 3;
-    const REGISTER_EVENTFD: libc::c_uint = 4;
-    const UNREGISTER_EVENTFD: libc::c_uint = 5;
+    pub(crate) const REGISTER_EVENTFD: libc::c_uint = 4;
+    pub(crate) const UNREGISTER_EVENTFD: libc::c_uint = 5;
     const REGISTER_FILES_UPDATE: libc::c_uint = 6;
-    const REGISTER_EVENTFD_ASYNC: libc::c_uint = 7;
-    const REGISTER_PROBE: libc::c_uint = 8;
+    pub(crate) const REGISTER_EVENTFD_ASYNC: libc::c_uint = 7;
+    pub(crate) const REGISTER_PROBE: libc::c_uint = 8;
     const REGISTER_PERSONALITY: libc::c_uint = 9;
     const UNREGISTER_PERSONALITY: libc::c_uint = 10;
     const REGISTER_RESTRICTIONS: libc::c_uint = 11;
     const REGISTER_ENABLE_RINGS: libc::c_uint = 12;
-
-    #[inline]
-    pub(crate) fn new(sq: sq::Queue<'a>, cq: cq::Queue<'a>, flags: Setup, fd: Fd) -> Self {
+    const REGISTER_IOWQ_AFF: libc::c_uint = 17;
+    const UNREGISTER_IOWQ_AFF: libc::c_uint = 18;
+    const REGISTER_IOWQ_MAX_WORKERS: libc::c_uint = 19;
+    const REGISTER_SYNC_CANCEL: libc::c_uint = 24;
+
+    #[inline]
+    pub(crate) fn new(
+        sq: sq::Queue<'a>,
+        cq: cq::Queue<'a>,
+        flags: Setup,
+        features: Feat,
+        fd: Fd,
+        wq: Option<Arc<Fd>>,
+        backend: Rc<dyn Backend>,
+    ) -> Self {
         Self {
             sq,
             cq,
             flags,
-            fd,
-            ts: libc::timespec {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
+            features,
+            fd: Arc::new(fd),
+            wq,
+            timeout: op::Timeout::after(Duration::from_secs(0)),
+            sqpoll_wakeups: 0,
+            enters: 0,
+            completed: 0,
+            backend,
         }
     }
 
@@ -227,11 +340,38 @@ impl<'a> Uring<'a> {
         UringBuilder::new(entries)
     }
 
+    #[inline]
+    pub(crate) fn backend(&self) -> &Rc<dyn Backend> {
+        &self.backend
+    }
+
+    #[inline]
+    pub(crate) fn shared_fd(&self) -> &Arc<Fd> {
+        &self.fd
+    }
+
+    #[inline]
+    pub fn handle(&self) -> RingHandle {
+        RingHandle(self.fd.clone())
+    }
+
+    // Whether this ring was attached to the io-wq of another ring.
+    #[inline]
+    pub fn shares_wq(&self) -> bool {
+        self.wq.is_some()
+    }
+
     #[inline]
     pub(crate) unsafe fn register(&self, opcode: u32, arg: *const u8, nr_args: u32) -> Result<()> {
-        sys::io_uring_register(self.fd.as_raw_fd(), opcode, arg, nr_args)
+        self.backend
+            .register(self.fd.as_raw_fd(), opcode, arg, nr_args)
+            .map(drop)
     }
 
+    /// # Safety
+    ///
+    /// The buffers must stay valid and must not be accessed while an op
+    /// reads or writes them, until they are unregistered or the ring drops.
     #[inline]
     pub unsafe fn register_buffers(&self, bufs: &[IoSliceMut]) -> Result<()> {
         self.register(
@@ -241,11 +381,18 @@ impl<'a> Uring<'a> {
         )
     }
 
+    /// # Safety
+    ///
+    /// No op using a registered buffer may be in flight.
     #[inline]
     pub unsafe fn unregister_buffers(&self) -> Result<()> {
         self.register(Self::UNREGISTER_BUFFERS, ptr::null(), 0)
     }
 
+    /// # Safety
+    ///
+    /// Each fd must be open for the call; the kernel holds its own
+    /// references afterwards.
     #[inline]
     pub unsafe fn register_files(&self, fds: &[RawFd]) -> Result<()> {
         self.register(
@@ -255,11 +402,17 @@ impl<'a> Uring<'a> {
         )
     }
 
+    /// # Safety
+    ///
+    /// No op using a registered file may be in flight.
     #[inline]
     pub unsafe fn unregister_files(&self) -> Result<()> {
         self.register(Self::UNREGISTER_FILES, ptr::null(), 0)
     }
 
+    /// # Safety
+    ///
+    /// As for `register_files`.
     #[inline]
     pub unsafe fn register_files_update(&self, offset: u32, fds: &[RawFd]) -> Result<()> {
         // io_uring_files_update
@@ -284,16 +437,26 @@ impl<'a> Uring<'a> {
         )
     }
 
+    /// # Safety
+    ///
+    /// `event_fd` must be an eventfd that stays open until it is
+    /// unregistered, see EventfdNotifier.
     #[inline]
     pub unsafe fn register_eventfd(&self, event_fd: RawFd) -> Result<()> {
         self.register(Self::REGISTER_EVENTFD, &event_fd as *const _ as *const _, 1)
     }
 
+    /// # Safety
+    ///
+    /// The caller must own the registration, since a ring has a single one.
     #[inline]
     pub unsafe fn unregister_eventfd(&self) -> Result<()> {
         self.register(Self::UNREGISTER_EVENTFD, ptr::null(), 0)
     }
 
+    /// # Safety
+    ///
+    /// As for `register_eventfd`.
     #[inline]
     pub unsafe fn register_eventfd_async(&self, event_fd: RawFd) -> Result<()> {
         self.register(
@@ -303,11 +466,18 @@ impl<'a> Uring<'a> {
         )
     }
 
+    /// # Safety
+    ///
+    /// The credentials of the task are shared with every op that names the
+    /// returned personality.
     #[inline]
     pub unsafe fn register_personality(&self) -> Result<()> {
         self.register(Self::REGISTER_PERSONALITY, ptr::null(), 0)
     }
 
+    /// # Safety
+    ///
+    /// No op using personality `id` may be in flight.
     #[inline]
     pub unsafe fn unregister_personality(&self, id: i32) -> Result<()> {
         self.register(Self::UNREGISTER_PERSONALITY, ptr::null(), id as u32)
@@ -335,11 +505,89 @@ impl<'a> Uring<'a> {
         unsafe {
             let ptr = alloc_zeroed(layout);
             probe = Box::from_raw(ptr as *mut Probe);
-            sys::io_uring_register(self.fd.as_raw_fd(), Self::REGISTER_PROBE, ptr, 256)?;
+            self.backend
+                .register(self.fd.as_raw_fd(), Self::REGISTER_PROBE, ptr, 256)?;
         }
         Ok(probe)
     }
 
+    // Restricts the io-wq workers of this ring to `cpus`.
+    #[inline]
+    pub fn register_iowq_aff(&self, cpus: &libc::cpu_set_t) -> Result<()> {
+        unsafe {
+            self.register(
+                Self::REGISTER_IOWQ_AFF,
+                cpus as *const _ as *const _,
+                mem::size_of::<libc::cpu_set_t>() as u32,
+            )
+        }
+    }
+
+    #[inline]
+    pub fn unregister_iowq_aff(&self) -> Result<()> {
+        unsafe { self.register(Self::UNREGISTER_IOWQ_AFF, ptr::null(), 0) }
+    }
+
+    // Sets the maximum number of bounded and unbounded io-wq workers, 0
+    // leaving a limit unchanged. Returns the previous limits.
+    pub fn register_iowq_max_workers(&self, bounded: u32, unbounded: u32) -> Result<(u32, u32)> {
+        let mut values = [bounded, unbounded];
+        unsafe {
+            self.register(
+                Self::REGISTER_IOWQ_MAX_WORKERS,
+                values.as_mut_ptr() as *const _,
+                2,
+            )?;
+        }
+        Ok((values[0], values[1]))
+    }
+
+    // Cancels the requests matched by `cancel` and waits for them to
+    // complete, for at most `timeout` if given. Returns the number of
+    // requests canceled.
+    pub fn sync_cancel(&self, cancel: &Cancel, timeout: Option<Duration>) -> Result<u32> {
+        let ts = match timeout {
+            Some(dur) => op::timespec(dur),
+            None => libc::timespec {
+                tv_sec: -1,
+                tv_nsec: -1,
+            },
+        };
+        let reg = SyncCancelReg::new(cancel, ts);
+        unsafe {
+            self.backend.register(
+                self.fd.as_raw_fd(),
+                Self::REGISTER_SYNC_CANCEL,
+                &reg as *const _ as *const _,
+                1,
+            )
+        }
+    }
+
+    // Submits an async cancel of every in-flight request on `fd`, along with
+    // any sqes already queued, and returns the number submitted as `submit`
+    // does. The completion of the cancel itself is not returned to the
+    // caller.
+    #[inline]
+    pub fn cancel_fd(&mut self, fd: RawFd) -> Result<u32> {
+        self.submit_cancel(&mut Cancel::fd(fd).all())
+    }
+
+    // Submits an async cancel of every in-flight request, along with any sqes
+    // already queued, as `cancel_fd` does.
+    #[inline]
+    pub fn cancel_all(&mut self) -> Result<u32> {
+        self.submit_cancel(&mut Cancel::any().all())
+    }
+
+    fn submit_cancel(&mut self, cancel: &mut Cancel) -> Result<u32> {
+        match unsafe { cancel.prepare(&mut self.sq) } {
+            Some(sqe) => sqe.set_user_data(UserData::CANCEL.into()),
+            None => return Err(Error::from_raw_os_error(libc::EAGAIN)),
+        }
+        self.submit()
+    }
+
     pub fn dontfork(&self) -> Result<()> {
         unsafe {
             let sqes = self.as_sq().sqes();
@@ -364,21 +612,52 @@ impl<'a> Uring<'a> {
         Ok(())
     }
 
+    /// # Safety
+    ///
+    /// As for `Op::prepare`.
     #[inline]
-    pub unsafe fn prepare<T: Op>(&mut self, op: &T) -> Option<&mut sq::Entry> {
+    pub unsafe fn prepare<T: Op>(&mut self, op: &mut T) -> Option<&mut sq::Entry> {
         op.prepare(self.as_sq_mut())
     }
 
+    // Whether the SQPOLL thread has gone idle and needs a wakeup to pick up
+    // new sqes. Always `false` without Setup::SQPOLL.
+    #[inline]
+    pub fn sqpoll_asleep(&self) -> bool {
+        self.flags.contains(Setup::SQPOLL) && self.sq.need_wakeup()
+    }
+
+    // Number of times submission had to wake up the SQPOLL thread.
+    #[inline]
+    pub fn sqpoll_wakeups(&self) -> u64 {
+        self.sqpoll_wakeups
+    }
+
+    // Waits for the SQPOLL thread to make room in a full SQ ring. Returns
+    // immediately without Setup::SQPOLL or if there is room already.
+    pub fn sqring_wait(&mut self) -> Result<()> {
+        if !self.flags.contains(Setup::SQPOLL) || self.sq.space_left() > 0 {
+            return Ok(());
+        }
+        self.enter(0, 0, &Enter::SQ_WAIT).map(drop)
+    }
+
     #[inline]
     pub fn submit(&mut self) -> Result<u32> {
         self.submit_and_wait(0)
     }
 
+    // Submits the sqes queued. Cqes held back because the CQ ring was full
+    // are flushed into it on the way.
+    //
+    // Fails with EBUSY while the kernel holds back as many cqes as it can;
+    // the sqes stay queued until completions are reaped to make room.
     pub fn submit_and_wait(&mut self, wait_nr: u32) -> Result<u32> {
         let submitted = self.sq.flush();
         let mut flags = Enter::empty();
-        let n = if self.need_enter(&mut flags) || wait_nr > 0 {
-            if wait_nr > 0 || self.flags.contains(Setup::IOPOLL) {
+        let cq_overflow_flush = self.cq_ring_needs_flush();
+        let n = if self.need_enter(&mut flags) || wait_nr > 0 || cq_overflow_flush {
+            if wait_nr > 0 || cq_overflow_flush || self.flags.contains(Setup::IOPOLL) {
                 flags.insert(Enter::GETEVENTS);
             }
             self.enter(submitted, wait_nr, &flags)?
@@ -388,14 +667,139 @@ impl<'a> Uring<'a> {
         Ok(n)
     }
 
+    // Hands every completion in the CQ ring to `f`. With Feat::NODROP, the
+    // cqes held back because the ring was full follow, the kernel being
+    // entered only to flush them. Returns the number of completions reaped.
+    pub fn reap<F>(&mut self, mut f: F) -> Result<u32>
+    where
+        F: FnMut(cq::Entry),
+    {
+        let mut n = 0;
+        let mut flushed = false;
+        loop {
+            let reaped = n;
+            while let Some(cqe) = self.cq.peek_cqe()? {
+                let cqe = *cqe;
+                self.cq.advance(1);
+                self.completed += 1;
+                #[cfg(feature = "tracing")]
+                self.sq.tracer.completed(&cqe);
+                f(cqe);
+                n += 1;
+            }
+            if flushed && n == reaped || !self.cq_ring_needs_flush() {
+                return Ok(n);
+            }
+            self.enter(0, 0, &Enter::GETEVENTS)?;
+            flushed = true;
+        }
+    }
+
+    // The state of the ring as the kernel reports it, to debug a ring that
+    // seems stuck.
+    pub fn fdinfo(&self) -> Result<FdInfo> {
+        let path = format!("/proc/self/fdinfo/{}", self.fd.as_raw_fd());
+        Ok(FdInfo::parse(&fs::read_to_string(path)?))
+    }
+
+    // A snapshot of the occupancy and counters of the ring.
+    pub fn stats(&self) -> Stats {
+        let ops = match self.sq.op_counts() {
+            Some(counts) => Box::new(*counts),
+            None => Box::new([0; 256]),
+        };
+        Stats {
+            sq_entries: self.sq.entries(),
+            sq_ready: self.sq.ready(),
+            sq_unsubmitted: self.sq.unsubmitted(),
+            sq_dropped: self.sq.dropped(),
+            cq_entries: self.cq.entries(),
+            cq_ready: self.cq.ready(),
+            cq_overflow: self.cq.overflow(),
+            sqpoll_wakeups: self.sqpoll_wakeups,
+            flushed: self.sq.flushed(),
+            enters: self.enters,
+            completed: self.completed,
+            ops,
+        }
+    }
+
+    // Counts the sqes flushed by opcode, for Stats::flushed_op and
+    // Stats::ops. Off by default, as it costs a pass over the sqes on each
+    // flush; turning it on starts the counts from zero.
+    #[inline]
+    pub fn count_ops(&mut self, on: bool) {
+        self.sq.count_ops(on);
+    }
+
+    // Records the latency of each op from prepare to completion, for
+    // `export_latency`. Off by default, as it costs a clock read and a map
+    // update per op.
+    #[cfg(feature = "tracing")]
+    #[inline]
+    pub fn record_latency(&mut self, on: bool) {
+        self.sq.tracer.set_latency(on);
+    }
+
+    // Hands the latency histogram of each op completed so far to `f`, see
+    // `record_latency`.
+    #[cfg(feature = "tracing")]
+    pub fn export_latency<F>(&self, mut f: F)
+    where
+        F: FnMut(Code, &crate::Histogram),
+    {
+        for (&opcode, histogram) in self.sq.tracer.histograms() {
+            if let Some(code) = Code::from_u8(opcode) {
+                f(code, histogram);
+            }
+        }
+    }
+
+    // The setup flags the ring was built with, less those dropped by
+    // UringBuilder::best_effort.
+    #[inline]
+    pub fn setup_flags(&self) -> Setup {
+        self.flags
+    }
+
+    #[inline]
+    pub fn features(&self) -> Feat {
+        self.features
+    }
+
+    // Whether the kernel holds back cqes for a full CQ ring rather than
+    // dropping them, see `overflowed`.
+    #[inline]
+    pub fn nodrop(&self) -> bool {
+        self.features.contains(Feat::NODROP)
+    }
+
+    // Completions dropped since the last call, if any. Without
+    // Feat::NODROP every cqe posted to a full CQ ring is dropped; with it
+    // only those the kernel has no memory left to hold back.
+    #[inline]
+    pub fn overflowed(&mut self) -> Option<cq::Overflowed> {
+        self.cq.take_overflowed()
+    }
+
+    // A PollAdd on the fd of this ring, to be submitted on a parent ring. It
+    // completes once this ring has completions to reap.
+    #[inline]
+    pub fn poll_op(&self) -> op::PollAdd {
+        op::PollAdd {
+            fd: self.fd.as_raw_fd(),
+            poll_mask: libc::POLLIN as u32,
+        }
+    }
+
     #[inline]
     pub fn wait_cqe_nr(&mut self, wait_nr: u32) -> Result<cq::Entry> {
-        self.get_cqe(0, wait_nr, None)
+        self.get_cqe(0, wait_nr, None, None)
     }
 
     #[inline]
     pub fn wait_cqe(&mut self) -> Result<cq::Entry> {
-        self.get_cqe(0, 1, None)
+        self.get_cqe(0, 1, None, None)
     }
 
     pub fn wait_cqes(
@@ -406,27 +810,25 @@ impl<'a> Uring<'a> {
     ) -> Result<cq::Entry> {
         let mut to_submit = 0;
         if let Some(dur) = timeout {
-            self.ts = libc::timespec {
-                tv_sec: dur.as_secs() as libc::time_t,
-                tv_nsec: dur.subsec_nanos() as libc::c_long,
-            };
-            match unsafe {
-                op::Timeout {
-                    ts: &self.ts,
-                    count: wait_nr,
-                    flags: 0,
-                }
-                .prepare(&mut self.sq)
-            } {
+            // Since 5.11 the timeout is handed to io_uring_enter(2), before
+            // a Timeout sqe ends the wait
+            if self.features.contains(Feat::EXT_ARG) {
+                let ts = op::timespec(dur);
+                to_submit = self.sq.flush();
+                return self.get_cqe(to_submit, wait_nr, sigmask, Some(&ts));
+            }
+            // Kept in self until get_cqe() has submitted it.
+            self.timeout = op::Timeout::after(dur).count(wait_nr);
+            match unsafe { self.timeout.prepare(&mut self.sq) } {
                 Some(sqe) => {
-                    sqe.set_user_data(cq::Queue::UDATA_TIMEOUT);
+                    sqe.set_user_data(UserData::TIMEOUT.into());
                     to_submit = self.as_sq_mut().flush();
                 }
                 None => return Err(Error::from_raw_os_error(libc::EAGAIN)),
             }
         }
 
-        self.get_cqe(to_submit, wait_nr, sigmask)
+        self.get_cqe(to_submit, wait_nr, sigmask, None)
     }
 
     #[inline]
@@ -454,27 +856,34 @@ impl<'a> Uring<'a> {
         &mut self.cq
     }
 
+    // Rewrites the results of ops submitted from now on, as the rules of
+    // `faults` dictate. None stops injecting.
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_faults(&mut self, faults: Option<crate::FaultInjector>) {
+        self.sq.faults = faults.clone();
+        self.cq.faults = faults;
+    }
+
     fn get_cqe(
         &mut self,
         mut submit: u32,
         to_wait: u32,
         sigmask: Option<&libc::sigset_t>,
+        ts: Option<&libc::timespec>,
     ) -> Result<cq::Entry> {
         let mut wait_nr = to_wait;
-        let mut ret = 0;
         loop {
+            let mut ret = 0;
             let mut cq_overflow_flush = false;
             let mut flags = Enter::empty();
             let peeked = match self.cq.peek_cqe()? {
                 Some(cqe) => {
-                    if wait_nr > 0 {
-                        wait_nr -= 1;
-                    }
+                    wait_nr = wait_nr.saturating_sub(1);
                     Some(*cqe)
                 }
                 None => {
                     if to_wait == 0 && submit == 0 {
-                        cq_overflow_flush = self.sq.cq_ring_needs_flush();
+                        cq_overflow_flush = self.cq_ring_needs_flush();
                         if !cq_overflow_flush {
                             return Err(Error::from_raw_os_error(libc::EAGAIN));
                         }
@@ -490,7 +899,10 @@ impl<'a> Uring<'a> {
                 self.need_enter(&mut flags);
             }
             if wait_nr > 0 || submit > 0 || cq_overflow_flush {
-                ret = self.penter(submit, wait_nr, &flags, sigmask)?
+                ret = match ts {
+                    Some(ts) => self.enter_ext(submit, wait_nr, &flags, sigmask, ts)?,
+                    None => self.penter(submit, wait_nr, &flags, sigmask)?,
+                };
             }
             if ret == submit {
                 submit = 0;
@@ -507,49 +919,91 @@ impl<'a> Uring<'a> {
             }
             if let Some(cqe) = peeked {
                 self.cq.advance(1);
+                self.completed += 1;
+                #[cfg(feature = "tracing")]
+                self.sq.tracer.completed(&cqe);
                 return Ok(cqe);
             }
         }
     }
 
+    // Whether cqes are held back for the CQ ring, to be flushed by entering
+    // with GETEVENTS
     #[inline]
-    fn need_enter(&self, flags: &mut Enter) -> bool {
+    fn cq_ring_needs_flush(&self) -> bool {
+        self.nodrop() && self.sq.cq_ring_needs_flush()
+    }
+
+    #[inline]
+    fn need_enter(&mut self, flags: &mut Enter) -> bool {
         if !self.flags.contains(Setup::SQPOLL) {
             return true;
         }
         if self.sq.need_wakeup() {
             flags.insert(Enter::SQ_WAKEUP);
+            self.sqpoll_wakeups += 1;
             return true;
         }
         false
     }
 
     #[inline]
-    fn enter(&self, to_submit: u32, min_complete: u32, flags: &Enter) -> Result<u32> {
-        unsafe { sys::io_uring_enter(self.fd.as_raw_fd(), to_submit, min_complete, flags.bits()) }
+    fn enter(&mut self, to_submit: u32, min_complete: u32, flags: &Enter) -> Result<u32> {
+        self.penter(to_submit, min_complete, flags, None)
     }
 
     #[inline]
     fn penter(
-        &self,
+        &mut self,
         to_submit: u32,
         min_complete: u32,
         flags: &Enter,
         sig: Option<&libc::sigset_t>,
     ) -> Result<u32> {
-        match sig {
-            Some(s) => unsafe {
-                sys::io_uring_penter(
-                    self.fd.as_raw_fd(),
-                    to_submit,
-                    min_complete,
-                    flags.bits(),
-                    s,
-                )
-            },
-            None => unsafe {
-                sys::io_uring_enter(self.fd.as_raw_fd(), to_submit, min_complete, flags.bits())
-            },
-        }
+        self.enters += 1;
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::trace_span!("enter", to_submit, min_complete, flags = flags.bits()).entered();
+        let res = unsafe {
+            self.backend.enter(
+                self.fd.as_raw_fd(),
+                to_submit,
+                min_complete,
+                flags.bits(),
+                sig,
+            )
+        };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(?res, "enter");
+        res
+    }
+
+    #[inline]
+    fn enter_ext(
+        &mut self,
+        to_submit: u32,
+        min_complete: u32,
+        flags: &Enter,
+        sig: Option<&libc::sigset_t>,
+        ts: &libc::timespec,
+    ) -> Result<u32> {
+        self.enters += 1;
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::trace_span!("enter", to_submit, min_complete, flags = flags.bits()).entered();
+        let arg = GeteventsArg::new(sig, ts);
+        let flags = *flags | Enter::EXT_ARG;
+        let res = unsafe {
+            self.backend.enter_ext(
+                self.fd.as_raw_fd(),
+                to_submit,
+                min_complete,
+                flags.bits(),
+                &arg,
+            )
+        };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(?res, "enter");
+        res
     }
 }
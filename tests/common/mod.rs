@@ -0,0 +1,21 @@
+// Helpers shared by the tests run against the Simulator
+#![allow(dead_code)]
+
+use ruyi_ur::op::{self, Op};
+use ruyi_ur::Uring;
+
+pub fn prepare_nop(uring: &mut Uring<'_>, user_data: u64) {
+    unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(user_data);
+}
+
+pub fn prepare_fsync(uring: &mut Uring<'_>, user_data: u64) {
+    let mut fsync = op::Fsync {
+        fd: 0,
+        flags: op::FsyncFlags::empty(),
+    };
+    unsafe { fsync.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(user_data);
+}
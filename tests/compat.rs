@@ -0,0 +1,60 @@
+#![cfg(feature = "tokio")]
+
+use std::os::unix::net::UnixStream;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::LocalSet;
+
+use ruyi_ur::compat::{File, Socket, TokioDriver};
+use ruyi_ur::Uring;
+
+#[tokio::test(flavor = "current_thread")]
+async fn compat_socket() {
+    let local = LocalSet::new();
+    local
+        .run_until(async {
+            let driver = TokioDriver::new(Uring::entries(8).try_build().unwrap()).unwrap();
+            let run = driver.spawn();
+
+            let (a, b) = UnixStream::pair().unwrap();
+            let mut a = Socket::new(driver.driver(), a);
+            let mut b = Socket::new(driver.driver(), b);
+
+            a.write_all(b"hello uring").await.unwrap();
+            a.shutdown().await.unwrap();
+            let mut received = Vec::new();
+            b.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"hello uring");
+
+            run.abort();
+        })
+        .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn compat_file() {
+    let local = LocalSet::new();
+    local
+        .run_until(async {
+            let driver = TokioDriver::new(Uring::entries(8).try_build().unwrap()).unwrap();
+            let run = driver.spawn();
+
+            let path = std::env::temp_dir().join(format!("ur-compat-{}", std::process::id()));
+            let mut file = File::new(driver.driver(), std::fs::File::create(&path).unwrap());
+            file.write_all(b"0123456789").await.unwrap();
+            file.flush().await.unwrap();
+            drop(file);
+
+            let mut file = File::new(driver.driver(), std::fs::File::open(&path).unwrap());
+            let mut head = [0u8; 4];
+            file.read_exact(&mut head).await.unwrap();
+            assert_eq!(&head, b"0123");
+            let mut rest = String::new();
+            file.read_to_string(&mut rest).await.unwrap();
+            assert_eq!(rest, "456789");
+
+            std::fs::remove_file(&path).unwrap();
+            run.abort();
+        })
+        .await;
+}
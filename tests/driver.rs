@@ -0,0 +1,71 @@
+use ruyi_ur::op;
+use ruyi_ur::{Driver, Uring};
+
+fn pipe() -> (i32, i32) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    (fds[0], fds[1])
+}
+
+#[test]
+fn driver_block_on() {
+    let driver = Driver::new(Uring::entries(4).try_build().unwrap());
+    let (rfd, wfd) = pipe();
+
+    let mut buf = vec![0u8; 8];
+    let read = op::Read {
+        fd: rfd,
+        buf: unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) },
+        offset: 0,
+    };
+    let read = unsafe { driver.submit(read, buf) };
+    let write = unsafe {
+        driver.submit(
+            op::Write {
+                fd: wfd,
+                data: b"ping",
+                offset: 0,
+            },
+            (),
+        )
+    };
+
+    let ((wcqe, ()), (rcqe, buf)) = driver.block_on(async { (write.await, read.await) });
+    assert_eq!(wcqe.res(), 4);
+    assert_eq!(rcqe.res(), 4);
+    assert_eq!(&buf[..4], b"ping");
+    assert_eq!(driver.pending(), 0);
+
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+}
+
+#[test]
+fn driver_drop_completion() {
+    let driver = Driver::new(Uring::entries(4).try_build().unwrap());
+    let (rfd, wfd) = pipe();
+
+    let mut buf = vec![0u8; 8];
+    let read = op::Read {
+        fd: rfd,
+        buf: unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) },
+        offset: 0,
+    };
+    drop(unsafe { driver.submit(read, buf) });
+    // The buffer is kept until the read completes
+    assert_eq!(driver.pending(), 1);
+
+    assert_eq!(unsafe { libc::write(wfd, b"x".as_ptr() as *const _, 1) }, 1);
+    driver.park().unwrap();
+    assert_eq!(driver.pending(), 0);
+
+    let (cqe, ()) = driver.block_on(unsafe { driver.submit(op::Nop, ()) });
+    assert_eq!(cqe.res(), 0);
+
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+}
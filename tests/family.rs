@@ -0,0 +1,40 @@
+use ruyi_ur::op::{self, Op};
+use ruyi_ur::{Uring, WqFamily};
+
+#[test]
+fn share_wq_with() {
+    let primary = Uring::entries(4).try_build().unwrap();
+    let mut uring = Uring::entries(4)
+        .share_wq_with(&primary)
+        .try_build()
+        .unwrap();
+    drop(primary);
+    assert!(uring.shares_wq());
+
+    unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+    assert_eq!(uring.wait_cqe().unwrap().user_data(), 1);
+}
+
+#[test]
+fn family_spawn_on() {
+    let primary = Uring::entries(4).try_build().unwrap();
+    let family = WqFamily::new(&primary, &Uring::entries(4));
+    drop(primary);
+
+    let handles = family
+        .spawn_on(&[0, 0], |cpu, mut uring| {
+            assert!(uring.shares_wq());
+            unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+                .unwrap()
+                .set_user_data(cpu as u64 + 1);
+            uring.submit().unwrap();
+            uring.wait_cqe().unwrap().user_data()
+        })
+        .unwrap();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap().unwrap(), 1);
+    }
+}
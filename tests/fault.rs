@@ -0,0 +1,88 @@
+#![cfg(all(feature = "fault-injection", feature = "simulator"))]
+
+mod common;
+
+use ruyi_ur::op::{self, Code, Op};
+use ruyi_ur::{Fault, FaultInjector, Uring};
+
+use common::{prepare_fsync, prepare_nop};
+
+// Submits `n` fsyncs completing with 100 and nops completing with 0, in
+// turn, and reaps them
+fn run(faults: &FaultInjector, n: u64) -> Vec<(u64, i32)> {
+    let (mut uring, sim) = Uring::entries(16).simulate().unwrap();
+    sim.on_submit(|sqe| {
+        Some(if sqe.opcode() == op::Fsync::CODE {
+            100
+        } else {
+            0
+        })
+    });
+    uring.inject_faults(Some(faults.clone()));
+    for user_data in 0..n {
+        if user_data % 2 == 0 {
+            prepare_fsync(&mut uring, user_data);
+        } else {
+            prepare_nop(&mut uring, user_data);
+        }
+    }
+    uring.submit().unwrap();
+    let mut cqes = Vec::new();
+    uring
+        .reap(|cqe| cqes.push((cqe.user_data(), cqe.res())))
+        .unwrap();
+    cqes
+}
+
+#[test]
+fn fault_error() {
+    let faults = FaultInjector::new(1);
+    faults.rule(Code::Fsync, Fault::Error(libc::EINTR), 1.0);
+    let cqes = run(&faults, 4);
+    assert_eq!(cqes, [(0, -libc::EINTR), (1, 0), (2, -libc::EINTR), (3, 0)]);
+    assert_eq!(faults.injected(), 2);
+
+    // Cleared rules leave results alone
+    faults.clear();
+    let cqes = run(&faults, 2);
+    assert_eq!(cqes, [(0, 100), (1, 0)]);
+    assert_eq!(faults.injected(), 2);
+}
+
+#[test]
+fn fault_short() {
+    let faults = FaultInjector::new(2);
+    faults.rule(Code::Fsync, Fault::Short, 1.0);
+    for (user_data, res) in run(&faults, 8) {
+        if user_data % 2 == 0 {
+            assert!(res > 0 && res < 100, "{}", res);
+        } else {
+            assert_eq!(res, 0);
+        }
+    }
+}
+
+#[test]
+fn fault_reorder() {
+    let faults = FaultInjector::new(3);
+    faults.rule(Code::Fsync, Fault::Reorder, 1.0);
+    let cqes = run(&faults, 2);
+    assert_eq!(cqes, [(1, 0), (0, 100)]);
+    assert_eq!(faults.injected(), 1);
+}
+
+#[test]
+fn fault_seeded() {
+    let chaos = |seed| {
+        let faults = FaultInjector::new(seed);
+        faults
+            .rule(Code::Fsync, Fault::Error(libc::EAGAIN), 0.3)
+            .rule(Code::Fsync, Fault::Short, 0.3)
+            .rule(Code::Nop, Fault::Error(libc::ECANCELED), 0.5);
+        run(&faults, 16)
+    };
+    let cqes = chaos(42);
+    assert_eq!(cqes, chaos(42));
+    assert_ne!(cqes, chaos(7));
+    assert!(cqes.iter().any(|&(_, res)| res != 0 && res != 100));
+}
@@ -0,0 +1,236 @@
+use std::ffi::CString;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use ruyi_ur::fs::{self, File, Metadata};
+use ruyi_ur::op::{self, LinkFlags, OpenHow, RenameFlags, ResolveFlags, StatxMask, XattrFlags};
+use ruyi_ur::{Driver, Uring};
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ur-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn file_read_write_at() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let path = temp_path("rw");
+
+    driver.block_on(async {
+        let file = File::create(&driver, &path).await.unwrap();
+        let (n, _) = file.write_at(b"hello world".to_vec(), 0).await;
+        assert_eq!(n.unwrap(), 11);
+        let (n, _) = file
+            .writev_at(vec![b"ur".to_vec(), b"ing".to_vec()], 6)
+            .await;
+        assert_eq!(n.unwrap(), 5);
+        file.sync_data().await.unwrap();
+        file.sync_all().await.unwrap();
+        file.close().await.unwrap();
+
+        let file = File::open(&driver, &path).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 11);
+        let (n, buf) = file.read_at(vec![0; 16], 6).await;
+        assert_eq!(&buf[..n.unwrap()], b"uring");
+        let (n, bufs) = file.readv_at(vec![vec![0; 5], vec![0; 6]], 0).await;
+        assert_eq!(n.unwrap(), 11);
+        assert_eq!(bufs.concat(), b"hello uring");
+    });
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_allocate_and_advise() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let path = temp_path("alloc");
+
+    driver.block_on(async {
+        let how = OpenHow::new(libc::O_RDWR | libc::O_CREAT | libc::O_CLOEXEC, 0o600);
+        let file = File::open_with(&driver, &path, how).await.unwrap();
+        file.allocate(0, 4096, 0).await.unwrap();
+        file.advise(0, 4096, libc::POSIX_FADV_SEQUENTIAL)
+            .await
+            .unwrap();
+        file.sync_range(0, 4096, libc::SYNC_FILE_RANGE_WRITE)
+            .await
+            .unwrap();
+        let metadata = file.metadata().await.unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 4096);
+        assert_eq!(metadata.mode(), 0o600);
+    });
+    assert_eq!(driver.pending(), 1); // close on drop
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_open_beneath() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let dir = std::fs::File::open(std::env::temp_dir()).unwrap();
+
+    driver.block_on(async {
+        let how = OpenHow::new(libc::O_RDONLY, 0).resolve(ResolveFlags::BENEATH);
+        let err = File::open_at(&driver, dir.as_raw_fd(), "../etc/passwd", how)
+            .await
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EXDEV));
+    });
+}
+
+#[test]
+fn namespace_ops() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let dir = temp_path("ns");
+    let (a, b) = (dir.join("a"), dir.join("b"));
+
+    driver.block_on(async {
+        fs::create_dir(&driver, &dir).await.unwrap();
+        assert!(dir.is_dir());
+        let err = fs::create_dir(&driver, &dir).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+        File::create(&driver, &a).await.unwrap();
+        fs::rename(&driver, &a, &b).await.unwrap();
+        assert!(!a.exists() && b.is_file());
+        fs::remove_file(&driver, &b).await.unwrap();
+        assert!(!b.exists());
+        let err = fs::remove_file(&driver, &b).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    });
+
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn link_and_exchange() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let dir = temp_path("link");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("a"), b"a").unwrap();
+    std::fs::write(dir.join("b"), b"b").unwrap();
+    let path = |name: &str| CString::new(dir.join(name).as_os_str().as_bytes()).unwrap();
+    let (a, b, hard, soft) = (path("a"), path("b"), path("hard"), path("soft"));
+
+    driver.block_on(async {
+        let op = op::LinkAt {
+            old_dfd: libc::AT_FDCWD,
+            old_path: &a,
+            new_dfd: libc::AT_FDCWD,
+            new_path: &hard,
+            flags: LinkFlags::empty(),
+        };
+        let (cqe, ()) = unsafe { driver.submit(op, ()) }.await;
+        assert_eq!(cqe.res(), 0);
+        let op = op::SymlinkAt {
+            target: &b,
+            new_dfd: libc::AT_FDCWD,
+            link_path: &soft,
+        };
+        let (cqe, ()) = unsafe { driver.submit(op, ()) }.await;
+        assert_eq!(cqe.res(), 0);
+        let op = op::RenameAt {
+            old_dfd: libc::AT_FDCWD,
+            old_path: &a,
+            new_dfd: libc::AT_FDCWD,
+            new_path: &b,
+            flags: RenameFlags::EXCHANGE,
+        };
+        let (cqe, ()) = unsafe { driver.submit(op, ()) }.await;
+        assert_eq!(cqe.res(), 0);
+    });
+
+    assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"b");
+    assert_eq!(std::fs::read(dir.join("hard")).unwrap(), b"a");
+    assert_eq!(std::fs::read(dir.join("soft")).unwrap(), b"a");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn xattrs() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let path = temp_path("xattr");
+    std::fs::write(&path, b"").unwrap();
+
+    driver.block_on(async {
+        let file = File::open(&driver, &path).await.unwrap();
+        match file
+            .set_xattr("user.small", b"crc", XattrFlags::CREATE)
+            .await
+        {
+            Err(err) if err.kind() == ErrorKind::Unsupported => return,
+            Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => return,
+            res => res.unwrap(),
+        }
+        assert_eq!(file.get_xattr("user.small").await.unwrap(), b"crc");
+        let err = file
+            .set_xattr("user.small", b"", XattrFlags::CREATE)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let err = file
+            .set_xattr("user.none", b"", XattrFlags::REPLACE)
+            .await
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODATA));
+
+        // Larger than the initial buffer, retried on ERANGE
+        let large = vec![7; 1000];
+        fs::set_xattr(&driver, &path, "user.large", &large, XattrFlags::empty())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::get_xattr(&driver, &path, "user.large").await.unwrap(),
+            large
+        );
+    });
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn statx_metadata() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let path = temp_path("statx");
+    let link = temp_path("statx-link");
+    std::fs::write(&path, b"statx").unwrap();
+    std::os::unix::fs::symlink(&path, &link).unwrap();
+
+    driver.block_on(async {
+        let metadata = fs::metadata(&driver, &link).await.unwrap();
+        assert!(metadata.file_type().is_file());
+        assert_eq!(metadata.len(), 5);
+        assert_eq!(metadata.nlink(), 1);
+        assert_eq!(
+            metadata.modified(),
+            std::fs::metadata(&path).unwrap().modified().unwrap()
+        );
+        let age = SystemTime::now()
+            .duration_since(metadata.modified())
+            .unwrap();
+        assert!(age < Duration::from_secs(60));
+        if let Some(created) = metadata.created() {
+            assert!(created <= metadata.modified());
+        }
+        assert!(fs::symlink_metadata(&driver, &link)
+            .await
+            .unwrap()
+            .is_symlink());
+
+        let file = File::open(&driver, &path).await.unwrap();
+        let op = op::Statx::fd(file.as_raw_fd(), StatxMask::BASIC_STATS | StatxMask::MNT_ID);
+        let (cqe, op) = unsafe { driver.submit_owned(op) }.await;
+        assert_eq!(cqe.res(), 0);
+        let fstat = Metadata::from_raw(*op.statx());
+        assert_eq!(fstat.ino(), metadata.ino());
+        assert_eq!(fstat.permissions().mode(), metadata.mode());
+        assert!(fstat.mount_id().is_some());
+    });
+
+    std::fs::remove_file(&link).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
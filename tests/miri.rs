@@ -0,0 +1,135 @@
+// Ops whose memory the kernel writes, driven through the Simulator standing
+// in for the kernel so that Miri can check the accesses:
+//
+//     cargo +nightly miri test --features simulator --test miri
+//
+// The handlers write through the pointers in the sqes, as the kernel does.
+#![cfg(feature = "simulator")]
+
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use ruyi_ur::op::{self, AtFlags, Code, Op, StatxMask};
+use ruyi_ur::{Driver, Uring};
+
+#[test]
+fn miri_read() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    sim.on_submit(|sqe| {
+        assert_eq!(sqe.opcode(), Code::Read as u8);
+        let n = sqe.len().min(5) as usize;
+        unsafe { ptr::copy_nonoverlapping(b"hello".as_ptr(), sqe.addr() as *mut u8, n) };
+        Some(n as i32)
+    });
+    let mut buf = [0u8; 8];
+    let mut read = op::Read {
+        fd: 0,
+        buf: &mut buf,
+        offset: 0,
+    };
+    unsafe { read.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+    let cqe = uring.wait_cqe().unwrap();
+    assert_eq!(cqe.res(), 5);
+    assert_eq!(&buf[..5], b"hello");
+}
+
+#[test]
+fn miri_recv() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    sim.on_submit(|sqe| {
+        assert_eq!(sqe.opcode(), Code::Recv as u8);
+        unsafe { ptr::write_bytes(sqe.addr() as *mut u8, b'x', sqe.len() as usize) };
+        Some(sqe.len() as i32)
+    });
+    let mut buf = vec![0u8; 16];
+    let mut recv = op::Recv {
+        sockfd: 0,
+        buf: &mut buf,
+        flags: 0,
+    };
+    unsafe { recv.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+    assert_eq!(uring.wait_cqe().unwrap().res(), 16);
+    assert!(buf.iter().all(|&b| b == b'x'));
+}
+
+#[test]
+fn miri_accept() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    sim.on_submit(|sqe| {
+        assert_eq!(sqe.opcode(), Code::Accept as u8);
+        let addr = sqe.addr() as *mut libc::sockaddr_in;
+        let addr_len = sqe.off() as *mut libc::socklen_t;
+        unsafe {
+            assert!(*addr_len as usize >= mem::size_of::<libc::sockaddr_in>());
+            (*addr).sin_family = libc::AF_INET as libc::sa_family_t;
+            (*addr).sin_port = 8080u16.to_be();
+            *addr_len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        }
+        Some(7)
+    });
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut addr_len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let mut accept = op::Accept {
+        fd: 0,
+        addr: unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr) },
+        addr_len: &mut addr_len,
+        flags: 0,
+    };
+    unsafe { accept.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+    assert_eq!(uring.wait_cqe().unwrap().res(), 7);
+    assert_eq!(addr_len as usize, mem::size_of::<libc::sockaddr_in>());
+    let addr = unsafe { &*(&storage as *const _ as *const libc::sockaddr_in) };
+    assert_eq!(addr.sin_family, libc::AF_INET as libc::sa_family_t);
+    assert_eq!(u16::from_be(addr.sin_port), 8080);
+}
+
+#[test]
+fn miri_statx() {
+    let (uring, sim) = Uring::entries(4).simulate().unwrap();
+    sim.on_submit(|sqe| {
+        assert_eq!(sqe.opcode(), Code::Statx as u8);
+        let statx = sqe.off() as *mut libc::statx;
+        unsafe {
+            (*statx).stx_mask = sqe.len();
+            (*statx).stx_size = 42;
+        }
+        Some(0)
+    });
+    let driver = Driver::new(uring);
+    let path = CString::new("file").unwrap();
+    let op = op::Statx::new(libc::AT_FDCWD, path, AtFlags::empty(), StatxMask::SIZE);
+    // The op is moved into the Completion after it is prepared
+    let (cqe, op) = driver.block_on(unsafe { driver.submit_owned(op) });
+    assert_eq!(cqe.res(), 0);
+    assert_eq!(op.statx().stx_size, 42);
+    assert_eq!(op.statx().stx_mask, StatxMask::SIZE.bits());
+}
+
+#[test]
+fn miri_timeout_enter_error() {
+    let (uring, sim) = Uring::entries(4).simulate().unwrap();
+    sim.on_submit(|sqe| {
+        assert_eq!(sqe.opcode(), Code::Timeout as u8);
+        let ts = unsafe { *(sqe.addr() as *const libc::timespec) };
+        Some(if ts.tv_sec == 5 { -libc::ETIME } else { 0 })
+    });
+    let driver = Driver::new(uring);
+    sim.fail_enter(libc::EAGAIN);
+    // The sqe stays queued past the failed enter, the timespec inline in
+    // the op with it
+    let completion = unsafe { driver.submit(op::Timeout::after(Duration::from_secs(5)), ()) };
+    driver.with_uring(|uring| uring.submit()).unwrap();
+    let (cqe, ()) = driver.block_on(completion);
+    assert_eq!(cqe.res(), -libc::ETIME);
+}
@@ -0,0 +1,142 @@
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+
+use ruyi_ur::net::{BufferGroup, UdpSocket, UnixStream};
+use ruyi_ur::{Cmsg, Driver, MsgHdr, Uring};
+
+fn setsockopt(fd: i32, level: i32, name: i32, val: i32) {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &val as *const _ as *const _,
+            mem::size_of::<i32>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(ret, 0);
+}
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+#[test]
+fn msg_pass_fd() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    driver.block_on(async {
+        let (a, b) = UnixStream::pair(&driver).unwrap();
+        let file = std::fs::File::open("/dev/null").unwrap();
+
+        let msg = MsgHdr::new()
+            .buf(b"fd".to_vec())
+            .cmsg(Cmsg::Rights(vec![file.as_raw_fd()]));
+        assert_eq!(a.send_msg(msg, 0).await.0.unwrap(), 2);
+
+        let msg = MsgHdr::new()
+            .buf(vec![0; 8])
+            .control_space(Cmsg::space_for_rights(1));
+        let (n, msg) = b.recv_msg(msg, 0).await;
+        assert_eq!(n.unwrap(), 2);
+        assert_eq!(msg.flags() & libc::MSG_CTRUNC, 0);
+        match msg.cmsgs().as_slice() {
+            [Cmsg::Rights(fds)] => {
+                assert_eq!(fds.len(), 1);
+                assert_ne!(fds[0], file.as_raw_fd());
+                unsafe { libc::close(fds[0]) };
+            }
+            cmsgs => panic!("unexpected cmsgs {:?}", cmsgs),
+        }
+    });
+}
+
+#[test]
+fn msg_udp_gso_gro() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    driver.block_on(async {
+        let tx = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let rx = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        setsockopt(rx.as_raw_fd(), libc::SOL_UDP, libc::UDP_GRO, 1);
+
+        let msg = MsgHdr::new()
+            .buf(vec![7; 300])
+            .name(rx.local_addr().unwrap())
+            .cmsg(Cmsg::UdpSegment(100));
+        assert_eq!(tx.send_msg(msg, 0).await.0.unwrap(), 300);
+
+        let msg = MsgHdr::new()
+            .buf(vec![0; 1024])
+            .name_space()
+            .control_space(Cmsg::space(mem::size_of::<i32>()));
+        let (n, msg) = rx.recv_msg(msg, 0).await;
+        let n = n.unwrap();
+        assert_eq!(msg.source().unwrap().unwrap(), tx.local_addr().unwrap());
+        if n == 300 {
+            assert_eq!(msg.cmsgs(), vec![Cmsg::UdpGro(100)]);
+        } else {
+            // Segments were not coalesced
+            assert_eq!(n, 100);
+        }
+    });
+}
+
+#[test]
+fn msg_rx_timestamp() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    driver.block_on(async {
+        let tx = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let rx = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let flags = libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+        setsockopt(
+            rx.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            flags as i32,
+        );
+
+        let (n, _) = tx.send_to(b"tick".to_vec(), rx.local_addr().unwrap()).await;
+        assert_eq!(n.unwrap(), 4);
+
+        let msg = MsgHdr::new()
+            .buf(vec![0; 16])
+            .control_space(Cmsg::space_for_timestamping());
+        let (n, msg) = rx.recv_msg(msg, 0).await;
+        assert_eq!(n.unwrap(), 4);
+        match msg.cmsgs().as_slice() {
+            [Cmsg::Timestamping(ts)] => assert!(ts[0].as_secs() > 0),
+            cmsgs => panic!("unexpected cmsgs {:?}", cmsgs),
+        }
+    });
+}
+
+#[test]
+fn msg_recv_multi() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    driver.block_on(async {
+        let tx = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let rx = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let rx_addr = rx.local_addr().unwrap();
+        // Header, name and control take 16 + 128 + 64 bytes of each buffer
+        let group = BufferGroup::new(&driver, 3, 4, 256).await.unwrap();
+        let msg = MsgHdr::new().name_space().control_space(64);
+        let mut stream = rx.recv_msg_multi(&group, msg);
+
+        for payload in [&b"a"[..], b"bc", &[9; 100]] {
+            tx.send_to(payload.to_vec(), rx_addr).await.0.unwrap();
+            let msg = stream.next().await.unwrap().unwrap();
+            assert!(msg.buffer_id().is_some());
+            let out = msg.out();
+            assert_eq!(out.source().unwrap().unwrap(), tx.local_addr().unwrap());
+            assert!(out.cmsgs().is_empty());
+            if payload.len() == 100 {
+                assert!(out.is_truncated());
+                assert_eq!(out.payload(), &payload[..48]);
+            } else {
+                assert!(!out.is_truncated());
+                assert_eq!(out.payload(), payload);
+                assert_eq!(out.payload_len(), payload.len());
+            }
+        }
+    });
+}
@@ -0,0 +1,64 @@
+use std::thread;
+
+use ruyi_ur::op::{self, MsgRingFlags, Op};
+use ruyi_ur::Uring;
+
+#[test]
+fn msg_ring_across_threads() {
+    let mut target = Uring::entries(4).try_build().unwrap();
+    let handle = target.handle();
+
+    thread::spawn(move || {
+        let mut uring = Uring::entries(4).try_build().unwrap();
+        let mut msg = op::MsgRing {
+            target: &handle,
+            res: 42,
+            user_data: 7,
+            flags: MsgRingFlags::empty(),
+        };
+        unsafe { msg.prepare(uring.as_sq_mut()) }.unwrap();
+        uring.submit().unwrap();
+    })
+    .join()
+    .unwrap();
+
+    let cqe = target.wait_cqe().unwrap();
+    assert_eq!(cqe.user_data(), 7);
+    assert_eq!(cqe.res(), 42);
+}
+
+#[test]
+fn msg_ring_send_fd() {
+    let mut source = Uring::entries(4).try_build().unwrap();
+    let mut target = Uring::entries(4).try_build().unwrap();
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    unsafe {
+        source.register_files(&fds).unwrap();
+        target.register_files(&[-1, -1]).unwrap();
+    }
+
+    let handle = target.handle();
+    let mut msg = op::MsgRingFd {
+        target: &handle,
+        src_index: 1,
+        dst_index: Some(0),
+        user_data: 9,
+        flags: MsgRingFlags::empty(),
+    };
+    unsafe { msg.prepare(source.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    source.submit().unwrap();
+    assert_eq!(source.wait_cqe().unwrap().res(), 0);
+
+    let cqe = target.wait_cqe().unwrap();
+    assert_eq!(cqe.user_data(), 9);
+    assert_eq!(cqe.res(), 0);
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Read;
+use std::net::{Shutdown, SocketAddr};
+use std::time::Duration;
+
+use ruyi_ur::net::{BufferGroup, TcpListener, TcpStream, UdpSocket, UnixListener, UnixStream};
+use ruyi_ur::{Driver, Uring};
+
+fn driver() -> Driver {
+    Driver::new(Uring::entries(16).try_build().unwrap())
+}
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+#[test]
+fn tcp_echo() {
+    let driver = driver();
+    driver.block_on(async {
+        let listener = TcpListener::bind(&driver, localhost()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = TcpStream::connect(&driver, addr).await.unwrap();
+        let (server, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+        assert_eq!(client.peer_addr().unwrap(), addr);
+
+        let (n, _) = client.write(b"ping".to_vec()).await;
+        assert_eq!(n.unwrap(), 4);
+        let (n, buf) = server.read(vec![0; 16]).await;
+        assert_eq!(&buf[..n.unwrap()], b"ping");
+    });
+}
+
+#[test]
+fn tcp_incoming() {
+    let driver = driver();
+    driver.block_on(async {
+        let listener = TcpListener::bind(&driver, localhost()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut incoming = listener.incoming();
+
+        let _a = TcpStream::connect(&driver, addr).await.unwrap();
+        let _b = TcpStream::connect(&driver, addr).await.unwrap();
+        for _ in 0..2 {
+            let stream = incoming.next().await.unwrap().unwrap();
+            assert_eq!(stream.local_addr().unwrap(), addr);
+        }
+    });
+}
+
+#[test]
+fn tcp_incoming_drop() {
+    let driver = driver();
+    driver.block_on(async {
+        let listener = TcpListener::bind(&driver, localhost()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Both are accepted as soon as the multishot accept is submitted
+        let _a = std::net::TcpStream::connect(addr).unwrap();
+        let mut b = std::net::TcpStream::connect(addr).unwrap();
+        let mut incoming = listener.incoming();
+        let _stream = incoming.next().await.unwrap().unwrap();
+
+        // The connection accepted but not taken is closed
+        drop(incoming);
+        driver.dispatch().unwrap();
+        b.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(b.read(&mut [0; 8]).unwrap(), 0);
+    });
+}
+
+#[test]
+fn udp_send_to_recv_from() {
+    let driver = driver();
+    driver.block_on(async {
+        let a = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let b = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        let (n, _) = a.send_to(b"hello".to_vec(), b_addr).await;
+        assert_eq!(n.unwrap(), 5);
+        let (res, buf) = b.recv_from(vec![0; 16]).await;
+        let (n, from) = res.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert_eq!(from, a.local_addr().unwrap());
+    });
+}
+
+#[test]
+fn unix_recv_select() {
+    let driver = driver();
+    driver.block_on(async {
+        let group = BufferGroup::new(&driver, 7, 4, 64).await.unwrap();
+        let (a, b) = UnixStream::pair(&driver).unwrap();
+
+        a.write(b"first".to_vec()).await.0.unwrap();
+        let buf = b.recv_select(&group).await.unwrap();
+        assert_eq!(&*buf, b"first");
+        assert!(buf.buffer_id().is_some());
+        drop(buf);
+
+        let mut stream = b.recv_multi(&group);
+        for msg in [&b"one"[..], b"two", b"three"] {
+            a.write(msg.to_vec()).await.0.unwrap();
+            assert_eq!(&*stream.next().await.unwrap().unwrap(), msg);
+        }
+        a.shutdown(Shutdown::Write).unwrap();
+        assert!(stream.next().await.is_none());
+    });
+}
+
+#[test]
+fn unix_listener() {
+    let driver = driver();
+    let path = std::env::temp_dir().join(format!("ur-sock-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    driver.block_on(async {
+        let listener = UnixListener::bind(&driver, &path).await.unwrap();
+        let client = UnixStream::connect(&driver, &path).await.unwrap();
+        let server = listener.accept().await.unwrap();
+
+        server.write(b"pong".to_vec()).await.0.unwrap();
+        let (n, buf) = client.read(vec![0; 8]).await;
+        assert_eq!(&buf[..n.unwrap()], b"pong");
+    });
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn udp_bind_in_use() {
+    let driver = driver();
+    driver.block_on(async {
+        let a = UdpSocket::bind(&driver, localhost()).await.unwrap();
+        // Without SO_REUSEADDR, as with std
+        let err = UdpSocket::bind(&driver, a.local_addr().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EADDRINUSE));
+    });
+}
@@ -0,0 +1,70 @@
+use std::os::unix::io::AsRawFd;
+
+use ruyi_ur::op::{self, Op};
+use ruyi_ur::{EventfdNotifier, Uring};
+
+fn readable(fd: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut pfd, 1, 1000) == 1 }
+}
+
+#[test]
+fn eventfd_notifier() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let notifier = EventfdNotifier::register(&uring).unwrap();
+
+    for user_data in 1..=2 {
+        unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+            .unwrap()
+            .set_user_data(user_data);
+    }
+    uring.submit().unwrap();
+
+    assert!(readable(notifier.as_raw_fd()));
+    let mut reaped = Vec::new();
+    let n = notifier
+        .drain(&mut uring, |cqe| reaped.push(cqe.user_data()))
+        .unwrap();
+    assert_eq!(n, 2);
+    assert_eq!(reaped, vec![1, 2]);
+    assert_eq!(notifier.clear().unwrap(), 0);
+}
+
+#[test]
+fn poll_ring_fd() {
+    let mut parent = Uring::entries(4).try_build().unwrap();
+    let mut child = Uring::entries(4).try_build().unwrap();
+
+    unsafe { child.poll_op().prepare(parent.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    parent.submit().unwrap();
+
+    unsafe { op::Nop.prepare(child.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(2);
+    child.submit().unwrap();
+
+    let cqe = parent.wait_cqe().unwrap();
+    assert_eq!(cqe.user_data(), 1);
+    assert!(cqe.res() & libc::POLLIN as i32 != 0);
+
+    let mut reaped = Vec::new();
+    child.reap(|cqe| reaped.push(cqe.user_data())).unwrap();
+    assert_eq!(reaped, vec![2]);
+}
+
+#[test]
+fn eventfd_notifier_drop() {
+    let uring = Uring::entries(4).try_build().unwrap();
+    let notifier = EventfdNotifier::register(&uring).unwrap();
+    let err = EventfdNotifier::register(&uring).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EBUSY));
+
+    drop(notifier);
+    EventfdNotifier::register_async(&uring).unwrap();
+}
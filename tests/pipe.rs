@@ -0,0 +1,85 @@
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+use ruyi_ur::pipe::{Pipeline, Totals};
+use ruyi_ur::{Driver, Uring};
+
+fn payload() -> Vec<u8> {
+    (0..200_000u32).map(|i| i as u8).collect()
+}
+
+fn reader(mut stream: UnixStream) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        buf
+    })
+}
+
+#[test]
+fn pipeline_copy() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let (mut src, from) = UnixStream::pair().unwrap();
+    let (to, dst) = UnixStream::pair().unwrap();
+
+    let data = payload();
+    let writer = {
+        let data = data.clone();
+        thread::spawn(move || src.write_all(&data).unwrap())
+    };
+    let received = reader(dst);
+
+    let pipeline = Pipeline::with_chunk(&driver, 16 * 1024).unwrap();
+    let totals = driver
+        .block_on(pipeline.copy(from.as_raw_fd(), to.as_raw_fd()))
+        .unwrap();
+    writer.join().unwrap();
+    drop(to);
+
+    let n = data.len() as u64;
+    assert_eq!(
+        totals,
+        Totals {
+            read: n,
+            written: n,
+            mirrored: 0
+        }
+    );
+    assert_eq!(received.join().unwrap(), data);
+}
+
+#[test]
+fn pipeline_copy_tee() {
+    let driver = Driver::new(Uring::entries(8).try_build().unwrap());
+    let (mut src, from) = UnixStream::pair().unwrap();
+    let (to, dst) = UnixStream::pair().unwrap();
+    let (mirror, mirror_dst) = UnixStream::pair().unwrap();
+
+    let data = payload();
+    let writer = {
+        let data = data.clone();
+        thread::spawn(move || src.write_all(&data).unwrap())
+    };
+    let received = reader(dst);
+    let mirrored = reader(mirror_dst);
+
+    let pipeline = Pipeline::new(&driver).unwrap();
+    let copy = pipeline.copy_tee(from.as_raw_fd(), to.as_raw_fd(), mirror.as_raw_fd());
+    let totals = driver.block_on(copy).unwrap();
+    writer.join().unwrap();
+    drop((to, mirror));
+
+    let n = data.len() as u64;
+    assert_eq!(
+        totals,
+        Totals {
+            read: n,
+            written: n,
+            mirrored: n
+        }
+    );
+    assert_eq!(received.join().unwrap(), data);
+    assert_eq!(mirrored.join().unwrap(), data);
+}
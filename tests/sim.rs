@@ -0,0 +1,271 @@
+#![cfg(feature = "simulator")]
+
+mod common;
+
+use std::cell::Cell;
+use std::io::Result;
+use std::ops::Range;
+use std::rc::Rc;
+
+use ruyi_ur::op::{self, Op};
+use ruyi_ur::{cq, Driver, Setup, Uring};
+
+use common::{prepare_fsync, prepare_nop};
+
+#[test]
+fn sim_completion_order() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    for user_data in 1..=3 {
+        prepare_fsync(&mut uring, user_data);
+    }
+    assert_eq!(uring.submit().unwrap(), 3);
+    let pending: Vec<_> = sim.pending().iter().map(|sqe| sqe.user_data()).collect();
+    assert_eq!(pending, [1, 2, 3]);
+    assert_eq!(sim.pending()[0].opcode(), op::Fsync::CODE);
+    assert!(uring.wait_cqe_nr(0).is_err());
+
+    assert!(sim.complete(3, 0));
+    assert!(!sim.complete(3, 0));
+    assert_eq!(sim.complete_next(-libc::EIO).unwrap().user_data(), 1);
+    assert!(sim.complete(2, 7));
+
+    let mut cqes = Vec::new();
+    uring
+        .reap(|cqe| cqes.push((cqe.user_data(), cqe.res())))
+        .unwrap();
+    assert_eq!(cqes, [(3, 0), (1, -libc::EIO), (2, 7)]);
+    assert!(sim.pending().is_empty());
+}
+
+#[test]
+fn sim_inject_errors() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    let seen = Rc::new(Cell::new(0));
+    let counter = seen.clone();
+    sim.on_submit(move |sqe| {
+        counter.set(counter.get() + 1);
+        Some(sqe.user_data() as i32)
+    });
+    sim.fail_next(libc::ECONNRESET);
+
+    prepare_nop(&mut uring, 1);
+    prepare_nop(&mut uring, 2);
+    uring.submit().unwrap();
+    let cqe = uring.wait_cqe().unwrap();
+    assert_eq!((cqe.user_data(), cqe.res()), (1, -libc::ECONNRESET));
+    let cqe = uring.wait_cqe().unwrap();
+    assert_eq!((cqe.user_data(), cqe.res()), (2, 2));
+    // The injected error bypasses the handler
+    assert_eq!(seen.get(), 1);
+}
+
+fn prepare_rounds(uring: &mut Uring<'_>, rounds: Range<u64>) -> Result<()> {
+    for round in rounds {
+        prepare_nop(uring, round * 2);
+        prepare_nop(uring, round * 2 + 1);
+        uring.submit()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn sim_cq_overflow() {
+    let (mut uring, sim) = Uring::entries(2).simulate().unwrap();
+    assert!(uring.nodrop());
+    // 2 sq entries and 4 cq entries
+    prepare_rounds(&mut uring, 0..4).unwrap();
+    assert_eq!(sim.overflowed(), 4);
+
+    let mut cqes = Vec::new();
+    for _ in 0..4 {
+        cqes.push(uring.wait_cqe().unwrap().user_data());
+    }
+    // Submitting flushes the overflowed cqes into the ring
+    uring.submit().unwrap();
+    assert_eq!(sim.overflowed(), 0);
+    uring.reap(|cqe| cqes.push(cqe.user_data())).unwrap();
+    assert_eq!(cqes, [0, 1, 2, 3, 4, 5, 6, 7]);
+
+    // So does reaping, in order
+    prepare_rounds(&mut uring, 0..4).unwrap();
+    cqes.clear();
+    assert_eq!(uring.reap(|cqe| cqes.push(cqe.user_data())).unwrap(), 8);
+    assert_eq!(cqes, [0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(uring.overflowed(), None);
+}
+
+#[test]
+fn sim_backlog_full() {
+    let (mut uring, sim) = Uring::entries(2).simulate().unwrap();
+    sim.set_backlog(2);
+    let err = prepare_rounds(&mut uring, 0..4).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EBUSY));
+    assert_eq!(sim.overflowed(), 2);
+
+    // The refused sqes stay queued until there is room
+    let mut cqes = Vec::new();
+    assert_eq!(uring.reap(|cqe| cqes.push(cqe.user_data())).unwrap(), 6);
+    uring.submit().unwrap();
+    uring.reap(|cqe| cqes.push(cqe.user_data())).unwrap();
+    assert_eq!(cqes, [0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(uring.overflowed(), None);
+}
+
+#[test]
+fn sim_cqe_lost() {
+    let (mut uring, sim) = Uring::entries(2).simulate().unwrap();
+    sim.set_backlog(1);
+    prepare_rounds(&mut uring, 0..3).unwrap();
+    assert_eq!(uring.overflowed(), Some(cq::Overflowed { lost: 1 }));
+    assert_eq!(uring.overflowed(), None);
+
+    let mut cqes = Vec::new();
+    uring.reap(|cqe| cqes.push(cqe.user_data())).unwrap();
+    assert_eq!(cqes, [0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn sim_driver_busy() {
+    let (uring, sim) = Uring::entries(2).simulate().unwrap();
+    sim.set_backlog(2);
+    let driver = Driver::new(uring);
+    // Nothing reaps until the ring and the backlog are full
+    let nops: Vec<_> = (0..8)
+        .map(|_| unsafe { driver.submit(op::Nop, ()) })
+        .collect();
+    for nop in nops {
+        let (cqe, ()) = driver.block_on(nop);
+        assert_eq!(cqe.res(), 0);
+    }
+    assert_eq!(driver.pending(), 0);
+    assert_eq!(driver.with_uring(|uring| uring.overflowed()), None);
+}
+
+#[test]
+fn sim_driver() {
+    let (uring, sim) = Uring::entries(4).simulate().unwrap();
+    let driver = Driver::new(uring);
+    assert!(driver.supports::<op::Socket>());
+
+    let fsync = op::Fsync {
+        fd: 0,
+        flags: op::FsyncFlags::empty(),
+    };
+    let completion = unsafe { driver.submit(fsync, "data") };
+    let nop = unsafe { driver.submit(op::Nop, ()) };
+    assert_eq!(driver.pending(), 2);
+
+    let (cqe, ()) = driver.block_on(nop);
+    assert_eq!(cqe.res(), 0);
+    let user_data = sim.pending()[0].user_data();
+    assert!(sim.complete(user_data, -libc::EBADF));
+    let (cqe, data) = driver.block_on(completion);
+    assert_eq!((cqe.res(), data), (-libc::EBADF, "data"));
+    assert_eq!(driver.pending(), 0);
+}
+
+#[test]
+fn sim_stats() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    uring.count_ops(true);
+    prepare_nop(&mut uring, 1);
+    prepare_fsync(&mut uring, 2);
+    prepare_fsync(&mut uring, 3);
+    let stats = uring.stats();
+    assert_eq!((stats.sq_entries, stats.cq_entries), (4, 8));
+    assert_eq!((stats.sq_unsubmitted, stats.flushed), (3, 0));
+
+    uring.submit().unwrap();
+    assert!(sim.complete(2, 0));
+    let stats = uring.stats();
+    assert_eq!((stats.sq_unsubmitted, stats.sq_ready), (0, 0));
+    assert_eq!((stats.flushed, stats.enters), (3, 1));
+    assert_eq!(stats.flushed_op(op::Code::Nop), 1);
+    assert_eq!(stats.flushed_op(op::Code::Fsync), 2);
+    assert_eq!(
+        stats.ops().collect::<Vec<_>>(),
+        [(op::Code::Nop, 1), (op::Code::Fsync, 2)]
+    );
+    assert_eq!((stats.cq_ready, stats.completed), (2, 0));
+
+    uring.reap(drop).unwrap();
+    let stats = uring.stats();
+    assert_eq!((stats.cq_ready, stats.completed), (0, 2));
+    assert_eq!((stats.sq_dropped, stats.cq_overflow), (0, 0));
+
+    // Without count_ops only the total is kept
+    uring.count_ops(false);
+    prepare_nop(&mut uring, 4);
+    uring.submit().unwrap();
+    let stats = uring.stats();
+    assert_eq!(stats.flushed, 4);
+    assert_eq!(stats.ops().count(), 0);
+}
+
+#[test]
+fn sim_best_effort() {
+    let err = Uring::entries(4).coop_taskrun().simulate().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+
+    let (uring, _sim) = Uring::entries(4)
+        .clamp()
+        .coop_taskrun()
+        .single_issuer()
+        .best_effort()
+        .simulate()
+        .unwrap();
+    assert_eq!(uring.setup_flags(), Setup::CLAMP);
+}
+
+#[test]
+fn sim_driver_enter_error() {
+    let (uring, sim) = Uring::entries(4).simulate().unwrap();
+    let driver = Driver::new(uring);
+    sim.fail_enter(libc::ENOMEM);
+    let fsync = op::Fsync {
+        fd: 0,
+        flags: op::FsyncFlags::empty(),
+    };
+    // The sqe is queued, so the op stays in flight with its data
+    let completion = unsafe { driver.submit(fsync, "data") };
+    assert_eq!(driver.pending(), 1);
+    let err = driver.dispatch().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOMEM));
+    assert!(sim.pending().is_empty());
+
+    driver.with_uring(|uring| uring.submit()).unwrap();
+    let user_data = sim.pending()[0].user_data();
+    assert!(sim.complete(user_data, 0));
+    let (cqe, data) = driver.block_on(completion);
+    assert_eq!((cqe.res(), data), (0, "data"));
+    assert_eq!(driver.pending(), 0);
+}
+
+#[test]
+fn sim_driver_overflowed() {
+    let (uring, sim) = Uring::entries(2).simulate().unwrap();
+    sim.set_backlog(0);
+    let driver = Driver::new(uring);
+    let fsyncs: Vec<_> = (0..5)
+        .map(|_| {
+            let fsync = op::Fsync {
+                fd: 0,
+                flags: op::FsyncFlags::empty(),
+            };
+            unsafe { driver.submit(fsync, ()) }
+        })
+        .collect();
+    // 4 cqes fill the CQ ring, the last one is lost
+    while sim.complete_next(0).is_some() {}
+
+    let err = driver.dispatch().unwrap_err();
+    let overflowed = err.get_ref().unwrap().downcast_ref::<cq::Overflowed>();
+    assert_eq!(overflowed, Some(&cq::Overflowed { lost: 1 }));
+    assert_eq!(driver.dispatch().unwrap(), 0);
+    for fsync in fsyncs.into_iter().take(4) {
+        let (cqe, ()) = driver.block_on(fsync);
+        assert_eq!(cqe.res(), 0);
+    }
+    // The op of the lost cqe never completes
+    assert_eq!(driver.pending(), 1);
+}
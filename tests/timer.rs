@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+
+use ruyi_ur::op::{self, Op};
+use ruyi_ur::{TimerWheel, Uring, UserData};
+
+#[test]
+fn timer_wheel_sleep() {
+    let mut uring = Uring::entries(8).try_build().unwrap();
+    let mut wheel = TimerWheel::new(Duration::from_millis(1), UserData::from_key(0));
+    let start = Instant::now();
+
+    for (i, ms) in [30u64, 10, 20, 5000].iter().enumerate() {
+        let deadline = start + Duration::from_millis(*ms);
+        wheel
+            .sleep_until(&mut uring, deadline, UserData::from_key(i + 1))
+            .unwrap();
+    }
+    let canceled = wheel
+        .sleep_until(&mut uring, start, UserData::from_key(9))
+        .unwrap();
+    assert!(wheel.cancel(canceled));
+    assert_eq!(wheel.len(), 4);
+    uring.submit().unwrap();
+
+    // The wakeups are posted to the CQ ring
+    let mut fired = Vec::new();
+    while fired.len() < 3 {
+        let cqe = uring.wait_cqe().unwrap();
+        if !wheel.process(&mut uring, &cqe).unwrap() {
+            assert_eq!(cqe.res(), -libc::ETIME);
+            fired.push(UserData::from(cqe.user_data()).key().unwrap());
+        }
+        uring.submit().unwrap();
+    }
+    assert_eq!(fired, vec![2, 3, 1]);
+    assert!(start.elapsed() >= Duration::from_millis(30));
+    assert_eq!(wheel.len(), 1);
+}
+
+#[test]
+fn timer_wheel_deadline() {
+    let mut uring = Uring::entries(8).try_build().unwrap();
+    let mut wheel = TimerWheel::new(Duration::from_millis(1), UserData::from_key(0));
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let mut buf = [0u8; 8];
+    let mut read = op::Read {
+        fd: fds[0],
+        buf: &mut buf,
+        offset: 0,
+    };
+    let user_data = UserData::from_key(1);
+    unsafe { read.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(user_data.into());
+    let deadline = Instant::now() + Duration::from_millis(10);
+    wheel.deadline(&mut uring, deadline, user_data).unwrap();
+    uring.submit().unwrap();
+
+    loop {
+        let cqe = uring.wait_cqe().unwrap();
+        if !wheel.process(&mut uring, &cqe).unwrap() {
+            assert_eq!(cqe.user_data(), user_data.into());
+            assert_eq!(cqe.res(), -libc::ECANCELED);
+            break;
+        }
+        uring.submit().unwrap();
+    }
+    assert!(wheel.is_empty());
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+#[test]
+fn timer_wheel_moved() {
+    let mut uring = Uring::entries(8).try_build().unwrap();
+    let mut wheel = TimerWheel::new(Duration::from_millis(1), UserData::from_key(0));
+    let start = Instant::now();
+
+    // Two updates of the Timeout before it is submitted
+    for (i, ms) in [50u64, 20, 10].iter().enumerate() {
+        let deadline = start + Duration::from_millis(*ms);
+        wheel
+            .sleep_until(&mut uring, deadline, UserData::from_key(i + 1))
+            .unwrap();
+    }
+    // The sqes refer to the wheel's heap, not to the wheel itself
+    let mut wheel = Box::new(wheel);
+    uring.submit().unwrap();
+
+    let mut fired = Vec::new();
+    while fired.is_empty() {
+        let cqe = uring.wait_cqe().unwrap();
+        if !wheel.process(&mut uring, &cqe).unwrap() {
+            fired.push(UserData::from(cqe.user_data()).key().unwrap());
+        }
+        uring.submit().unwrap();
+    }
+    assert_eq!(fired, vec![3]);
+    assert!(start.elapsed() >= Duration::from_millis(10));
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[test]
+fn timer_wheel_far_deadline() {
+    let mut uring = Uring::entries(8).try_build().unwrap();
+    let mut wheel = TimerWheel::new(Duration::from_nanos(1), UserData::from_key(0));
+
+    // A deadline past the reach of the wheel, clamped to MAX_TICKS ahead of
+    // a wheel that has advanced
+    std::thread::sleep(Duration::from_millis(2));
+    assert_eq!(wheel.expire(&mut uring).unwrap(), 0);
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    let key = wheel
+        .sleep_until(&mut uring, deadline, UserData::from_key(1))
+        .unwrap();
+    assert_eq!(wheel.expire(&mut uring).unwrap(), 0);
+    assert_eq!(wheel.len(), 1);
+    assert!(wheel.cancel(key));
+    assert!(wheel.is_empty());
+}
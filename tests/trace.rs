@@ -0,0 +1,124 @@
+#![cfg(all(feature = "tracing", feature = "simulator"))]
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ruyi_ur::op::{self, Code, Op};
+use ruyi_ur::Uring;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+// Collects the message and fd of every event and counts spans
+#[derive(Default)]
+struct Collector {
+    events: Mutex<Vec<(String, Option<i64>)>>,
+    spans: AtomicUsize,
+    next_id: AtomicU64,
+}
+
+struct Shared(Arc<Collector>);
+
+struct Fields<'a>(&'a mut (String, Option<i64>));
+
+impl Visit for Fields<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == "fd" {
+            self.0 .1 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 .0 = format!("{:?}", value);
+        }
+    }
+}
+
+impl Subscriber for Shared {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        self.0.spans.fetch_add(1, Ordering::Relaxed);
+        Id::from_u64(self.0.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = (String::new(), None);
+        event.record(&mut Fields(&mut fields));
+        self.0.events.lock().unwrap().push(fields);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn trace_events() {
+    let collector = Arc::new(Collector::default());
+    tracing::subscriber::with_default(Shared(collector.clone()), || {
+        let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+        sim.on_submit(|_| Some(0));
+        let mut fsync = op::Fsync {
+            fd: 5,
+            flags: op::FsyncFlags::empty(),
+        };
+        unsafe { fsync.prepare(uring.as_sq_mut()) }
+            .unwrap()
+            .set_user_data(1);
+        uring.submit().unwrap();
+        uring.reap(drop).unwrap();
+        // Latencies are not recorded unless asked for
+        uring.export_latency(|_, _| panic!("latency recorded"));
+    });
+    let events = collector.events.lock().unwrap();
+    let messages: Vec<_> = events.iter().map(|(message, _)| message.as_str()).collect();
+    assert_eq!(messages, ["prepare", "submit", "enter", "complete"]);
+    assert_eq!(events[3].1, Some(5));
+    assert_eq!(collector.spans.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn trace_latency() {
+    let (mut uring, sim) = Uring::entries(4).simulate().unwrap();
+    uring.record_latency(true);
+    for user_data in 0..3 {
+        let mut fsync = op::Fsync {
+            fd: 0,
+            flags: op::FsyncFlags::empty(),
+        };
+        unsafe { fsync.prepare(uring.as_sq_mut()) }
+            .unwrap()
+            .set_user_data(user_data);
+    }
+    unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(3);
+    uring.submit().unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+    for user_data in 0..3 {
+        assert!(sim.complete(user_data, 0));
+    }
+    uring.reap(drop).unwrap();
+
+    let mut exported = Vec::new();
+    uring.export_latency(|code, histogram| exported.push((code, histogram.clone())));
+    exported.sort_by_key(|(code, _)| *code as u8);
+    assert_eq!(exported.len(), 2);
+    let (code, nop) = &exported[0];
+    assert_eq!((*code, nop.count()), (Code::Nop, 1));
+    let (code, fsync) = &exported[1];
+    assert_eq!((*code, fsync.count()), (Code::Fsync, 3));
+    assert!(fsync.mean() >= Duration::from_millis(2));
+    assert!(fsync.quantile(0.5) >= Duration::from_millis(2));
+    assert!(fsync.quantile(1.0) <= fsync.max());
+    assert_eq!(fsync.buckets().map(|(_, n)| n).sum::<u64>(), 3);
+}
@@ -0,0 +1,69 @@
+use std::cell::Cell;
+
+use ruyi_ur::op::{self, Code, Op};
+use ruyi_ur::{cq, Dispatcher, Uring, UserData};
+
+#[test]
+fn user_data_encoding() {
+    let x = 42u32;
+    let ptr = UserData::from_ptr(&x as *const u32);
+    assert_eq!(ptr.as_ptr::<u32>(), Some(&x as *const u32));
+    assert_eq!(ptr.key(), None);
+
+    let key = UserData::from_key(7);
+    assert_eq!(key.key(), Some(7));
+    assert_eq!(key.op(), None);
+
+    let op = UserData::from_op(Code::Read, UserData::MAX_INDEX);
+    assert_eq!(op.op(), Some((Code::Read, UserData::MAX_INDEX)));
+    assert_eq!(op.as_ptr::<u8>(), None);
+
+    for user_data in &[ptr, key, op] {
+        assert!(!user_data.is_internal());
+        assert_eq!(UserData::from(u64::from(*user_data)), *user_data);
+    }
+    assert!(UserData::from(u64::MAX).is_internal());
+    assert!(UserData::from(u64::MAX - 15).is_internal());
+    // Raw values with the top bits set are left to the caller
+    assert!(!UserData::from(u64::MAX - 16).is_internal());
+    assert!(!UserData::from(0b11 << 62).is_internal());
+}
+
+#[test]
+fn dispatch_completions() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let nop_res = Cell::new(None);
+    let op_index = Cell::new(None);
+
+    let mut dispatcher = Dispatcher::new();
+    let key = dispatcher.register(|cqe: &cq::Entry| nop_res.set(Some(cqe.res())));
+    dispatcher.register_op(Code::Nop, |index, _| op_index.set(Some(index)));
+
+    let raw = Cell::new(None);
+    for user_data in &[
+        key,
+        UserData::from_op(Code::Nop, 3),
+        UserData::from(u64::MAX),
+        UserData::from(0b11 << 62),
+    ] {
+        let sqe = unsafe { op::Nop.prepare(uring.as_sq_mut()) }.unwrap();
+        sqe.set_user_data((*user_data).into());
+    }
+    uring.submit_and_wait(4).unwrap();
+
+    // The internal completion is consumed, the raw one is not dispatched
+    let n = uring
+        .reap(|cqe| {
+            if !dispatcher.dispatch(&cqe) {
+                raw.set(Some(cqe.user_data()));
+            }
+        })
+        .unwrap();
+    assert_eq!(n, 3);
+    assert_eq!(raw.get(), Some(0b11 << 62));
+    assert_eq!(nop_res.get(), Some(0));
+    assert_eq!(op_index.get(), Some(3));
+
+    assert!(dispatcher.unregister(key));
+    assert!(!dispatcher.unregister(key));
+}
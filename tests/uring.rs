@@ -1,4 +1,13 @@
-use ruyi_ur::{op, Uring};
+use std::time::{Duration, Instant};
+
+use ruyi_ur::op::{self, Code, Op};
+use ruyi_ur::{Capabilities, FdInfo, Setup, Uring};
+
+fn pipe() -> (i32, i32) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    (fds[0], fds[1])
+}
 
 #[test]
 fn uring_probe() {
@@ -6,4 +15,221 @@ fn uring_probe() {
     let probe = uring.probe().unwrap();
 
     assert!(probe.support::<op::Nop>());
+    assert!(probe.support_code(Code::Nop));
+    assert_eq!(probe.supported_ops().next(), Some(Code::Nop));
+    assert!(probe.supported_ops().all(|code| probe.support_code(code)));
+    let table = probe.to_string();
+    assert!(table
+        .lines()
+        .any(|line| line.contains(" Nop ") && line.ends_with("yes")));
+}
+
+#[test]
+fn uring_capabilities() {
+    let caps = Capabilities::detect().unwrap();
+    assert!(caps.nodrop());
+    assert!(caps.supports::<op::Nop>());
+    assert!(caps.supports_code(Code::Nop));
+    // Flags the kernel accepts are kept by a best-effort build
+    let uring = Uring::entries(4)
+        .submit_all()
+        .coop_taskrun()
+        .single_issuer()
+        .best_effort()
+        .try_build()
+        .unwrap();
+    for &flag in &[Setup::SUBMIT_ALL, Setup::COOP_TASKRUN, Setup::SINGLE_ISSUER] {
+        assert_eq!(
+            uring.setup_flags().contains(flag),
+            caps.supports_setup(flag)
+        );
+    }
+}
+
+#[test]
+fn uring_wait_timeout() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let start = Instant::now();
+    let err = uring
+        .wait_cqe_timeout(Some(Duration::from_millis(10)))
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ETIME));
+    assert!(start.elapsed() >= Duration::from_millis(10));
+
+    unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    let cqe = uring
+        .wait_cqe_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    assert_eq!((cqe.user_data(), cqe.res()), (1, 0));
+}
+
+#[test]
+fn uring_fdinfo() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+    let info = uring.fdinfo().unwrap();
+    assert_eq!(info.sq_thread, None);
+    // Ring positions are only reported from Linux 5.18
+    if let Some(cqes) = info.cqes {
+        assert_eq!(cqes, 1);
+        assert_eq!((info.sq_mask, info.cq_mask), (Some(3), Some(7)));
+        assert_eq!((info.cq_head, info.cq_tail), (Some(0), Some(1)));
+    }
+
+    let info = FdInfo::parse(
+        "SqMask:\t0x3\nSqHead:\t2\nSqThread:\t1234\nSqThreadCpu:\t-1\n\
+         UserFiles:\t0\nPollList:\nCqOverflowList:\n  \
+         user_data=7, res=-11, flags=2\n  user_data=8, res=0, flags=0\n",
+    );
+    assert_eq!((info.sq_mask, info.sq_head), (Some(3), Some(2)));
+    assert_eq!((info.sq_thread, info.sq_thread_cpu), (Some(1234), None));
+    assert_eq!((info.user_files, info.user_bufs), (Some(0), None));
+    let overflow: Vec<_> = info
+        .overflow
+        .iter()
+        .map(|cqe| (cqe.user_data(), cqe.res(), cqe.more()))
+        .collect();
+    assert_eq!(overflow, [(7, -libc::EAGAIN, true), (8, 0, false)]);
+}
+
+#[test]
+fn uring_cancel_fd() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let (rfd, wfd) = pipe();
+    let mut buf = [0u8; 8];
+    let mut read = op::Read {
+        fd: rfd,
+        buf: &mut buf,
+        offset: 0,
+    };
+    unsafe { read.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+
+    uring.cancel_fd(rfd).unwrap();
+    let cqe = uring.wait_cqe().unwrap();
+    assert_eq!(cqe.user_data(), 1);
+    assert_eq!(cqe.res(), -libc::ECANCELED);
+    assert!(uring.wait_cqe_nr(0).is_err());
+
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+}
+
+#[test]
+fn uring_sync_cancel() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let (rfd, wfd) = pipe();
+    let mut bufs = [[0u8; 8]; 2];
+    for buf in bufs.iter_mut() {
+        let mut read = op::Read {
+            fd: rfd,
+            buf,
+            offset: 0,
+        };
+        unsafe { read.prepare(uring.as_sq_mut()) }
+            .unwrap()
+            .set_user_data(1);
+    }
+    uring.submit().unwrap();
+
+    let cancel = op::Cancel::fd(rfd).all();
+    let n = uring
+        .sync_cancel(&cancel, Some(Duration::from_secs(1)))
+        .unwrap();
+    assert_eq!(n, 2);
+    for _ in 0..2 {
+        assert_eq!(uring.wait_cqe().unwrap().res(), -libc::ECANCELED);
+    }
+
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+}
+
+#[test]
+fn uring_timeout_at() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let start = Instant::now();
+    let mut timeout =
+        op::Timeout::at(start + Duration::from_millis(20)).flags(op::TimeoutFlags::ETIME_SUCCESS);
+    unsafe { timeout.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+
+    let cqe = uring.wait_cqe().unwrap();
+    assert_eq!(cqe.user_data(), 1);
+    assert_eq!(cqe.res(), -libc::ETIME);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+fn uring_timeout_update() {
+    let mut uring = Uring::entries(4).try_build().unwrap();
+    let start = Instant::now();
+    let mut timeout = op::Timeout::after(Duration::from_secs(10));
+    unsafe { timeout.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    let mut update = op::TimeoutUpdate {
+        user_data: 1,
+        ts: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 1_000_000,
+        },
+        absolute: false,
+        link: false,
+    };
+    unsafe { update.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(2);
+    uring.submit().unwrap();
+
+    let mut results = [uring.wait_cqe().unwrap(), uring.wait_cqe().unwrap()];
+    results.sort_by_key(|cqe| cqe.user_data());
+    assert_eq!(results[0].res(), -libc::ETIME);
+    assert_eq!(results[1].res(), 0);
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn uring_sqpoll_wakeup() {
+    let mut uring = Uring::entries(4).sqpoll_idle(1).try_build().unwrap();
+    assert_eq!(uring.sqpoll_wakeups(), 0);
+    uring.sqring_wait().unwrap();
+
+    let start = Instant::now();
+    while !uring.sqpoll_asleep() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    unsafe { op::Nop.prepare(uring.as_sq_mut()) }
+        .unwrap()
+        .set_user_data(1);
+    uring.submit().unwrap();
+    assert_eq!(uring.sqpoll_wakeups(), 1);
+    assert_eq!(uring.wait_cqe().unwrap().user_data(), 1);
+}
+
+#[test]
+fn uring_iowq_max_workers() {
+    let uring = Uring::entries(4).try_build().unwrap();
+    let (bounded, unbounded) = uring.register_iowq_max_workers(2, 0).unwrap();
+    assert!(bounded > 0 && unbounded > 0);
+    assert_eq!(uring.register_iowq_max_workers(0, 0).unwrap().0, 2);
+
+    let mut cpus: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(0, &mut cpus) };
+    uring.register_iowq_aff(&cpus).unwrap();
+    uring.unregister_iowq_aff().unwrap();
 }